@@ -11,6 +11,7 @@ pub const BLINK: &str = "\u{001B}[5m";
 pub const RAPID_BLINK: &str = "\u{001B}[6m";
 pub const REVERSE_VIDEO: &str = "\u{001B}[7m";
 pub const INVISIBLE_TEXT: &str = "\u{001B}[8m";
+pub const CROSSED_OUT: &str = "\u{001B}[9m";
 
 pub const BLACK: &str = "\u{001B}[30m";
 pub const RED: &str = "\u{001B}[31m";
@@ -34,3 +35,5 @@ pub const BACKGROUND_CYAN: &str = "\u{001B}[46m";
 pub const BACKGROUND_WHITE: &str = "\u{001B}[47m";
 
 // define your own strings and/or styles below
+pub const BACKGROUND_MENTION: &str = "\u{001b}[48;5;58m";
+pub const BACKGROUND_CODE: &str = "\u{001b}[48;5;235m";
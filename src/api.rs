@@ -0,0 +1,97 @@
+use std::{sync::OnceLock, time::Duration};
+
+use reqwest::StatusCode;
+use serde_json::Value;
+use serenity::{
+    http::Http,
+    model::{
+        channel::{ChannelCategory, GuildChannel},
+        id::{ChannelId, GuildId},
+    },
+};
+
+// Endpoints that serenity doesn't cover yet.
+const BASE: &str = "https://discord.com/api/v9";
+const MAX_TRIES: usize = 3; // how many times a rate limited request is sent before giving up
+
+fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        // requests run on short-lived runtimes, so connections aren't kept around for the next one
+        reqwest::Client::builder()
+            .pool_max_idle_per_host(0)
+            .build()
+            .unwrap_or_default()
+    })
+}
+/// These requests don't go through serenity's ratelimiter, so a 429 is waited out here instead.
+async fn get(http: &Http, path: &str) -> Result<Value, String> {
+    for _ in 0..MAX_TRIES {
+        let response = client()
+            .get(format!("{}{}", BASE, path))
+            .header("Authorization", &http.token)
+            .send()
+            .await
+            .map_err(|x| x.to_string())?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            let wait = response
+                .headers()
+                .get("retry-after")
+                .and_then(|x| x.to_str().ok())
+                .and_then(|x| x.parse::<f64>().ok())
+                .unwrap_or(1.0);
+            tokio::time::sleep(Duration::from_secs_f64(wait.max(0.0))).await;
+            continue;
+        }
+        return response
+            .error_for_status()
+            .map_err(|x| x.to_string())?
+            .json()
+            .await
+            .map_err(|x| x.to_string());
+    }
+    Err("Discord is rate limiting requests, try again later.".to_string())
+}
+/// Downloads a file that isn't an attachment, e.g. an embed's image.
+pub async fn download(url: &str) -> Result<Vec<u8>, String> {
+    let response = client().get(url).send().await.map_err(|x| x.to_string())?;
+    let bytes = response.error_for_status().map_err(|x| x.to_string())?.bytes().await.map_err(|x| x.to_string())?;
+    Ok(bytes.to_vec())
+}
+/// Turns a thread into a text channel, which is how it's treated everywhere else.
+/// `category_id` is the channel the thread belongs to.
+pub fn thread(mut v: Value) -> Option<GuildChannel> {
+    let map = v.as_object_mut()?;
+    if !matches!(map.get("type").and_then(|x| x.as_u64()), Some(10..=12)) {
+        return None;
+    }
+    map.insert("type".to_string(), Value::from(0));
+    map.entry("position").or_insert_with(|| Value::from(0));
+    map.entry("permission_overwrites").or_insert_with(|| Value::Array(Vec::new()));
+    serde_json::from_value(v).ok()
+}
+/// Turns a category back into the channel it was sent as.
+pub fn category(category: &ChannelCategory) -> Option<GuildChannel> {
+    serde_json::to_value(category).ok().and_then(|x| serde_json::from_value(x).ok())
+}
+fn threads(v: Value) -> Vec<GuildChannel> {
+    match v {
+        Value::Object(mut val) => match val.remove("threads") {
+            Some(Value::Array(val)) => val.into_iter().filter_map(thread).collect(),
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+/// Every thread in the guild that hasn't been archived.
+pub async fn active_threads(http: &Http, guild: GuildId) -> Result<Vec<GuildChannel>, String> {
+    get(http, &format!("/guilds/{}/threads/active", guild.0)).await.map(threads)
+}
+/// The most recently archived public threads of a channel.
+pub async fn archived_threads(http: &Http, channel: ChannelId) -> Result<Vec<GuildChannel>, String> {
+    get(http, &format!("/channels/{}/threads/archived/public", channel.0)).await.map(threads)
+}
+/// Fetches a channel, returning it only if it's a thread.
+pub async fn get_thread(http: &Http, channel: ChannelId) -> Result<Option<GuildChannel>, String> {
+    get(http, &format!("/channels/{}", channel.0)).await.map(thread)
+}
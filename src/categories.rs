@@ -1,7 +1,7 @@
 use std::io::Stdout;
 
 use crossterm::{queue, style::Print};
-use serenity::model::{channel::GuildChannel, id::GuildId};
+use serenity::model::{channel::GuildChannel, id::{ChannelId, GuildId}};
 use unicode_segmentation::UnicodeSegmentation;
 
 use crate::colors::SimpleColor;
@@ -9,7 +9,7 @@ use crate::servers::Unread;
 use crate::{
     ansi,
     channels::{self, Channels},
-    render::Grid,
+    render::{self, Grid},
 };
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
 
@@ -24,6 +24,12 @@ impl CategoryLabel {
             color: SimpleColor::new(),
         }
     }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn rename(&mut self, name: String) {
+        self.name = name;
+    }
     pub fn to_string(&self) -> String {
         self.color.to_ansi_value() + &self.name 
     }
@@ -81,8 +87,10 @@ impl Categories {
     }
     pub fn add(&mut self, name: String, pos: Option<usize>, ch: Option<GuildChannel>) {
         if let Some(pos) = pos {
-            if pos < self.current {
-                self.current += 1;
+            for index in [&mut self.current, &mut self.selected].iter_mut() {
+                if **index >= pos {
+                    **index += 1;
+                }
             }
             self.unread.insert(pos, Unread::Read);
             self.labels.insert(pos, CategoryLabel::new(name));
@@ -106,6 +114,68 @@ impl Categories {
         self.contents.remove(pos);
         self.flag = true;
     }
+    /// The position of a category, or of uncategorized channels if it isn't listed.
+    pub fn find(&self, category: Option<ChannelId>) -> usize {
+        self.contents
+            .iter()
+            .position(|x| x.id.is_some() && x.id.as_ref().map(|x| x.id) == category)
+            .unwrap_or(0)
+    }
+    /// Where a category with the given position belongs, going by the categories already here.
+    pub fn position_for(&self, position: i64) -> usize {
+        (1..self.labels.len())
+            .find(|&i| matches!(&self.contents[i].id, Some(val) if val.position > position))
+            .unwrap_or(self.labels.len())
+    }
+    /// Lists a category where its position puts it, or renames and moves it if it's already listed.
+    pub fn update(&mut self, category: GuildChannel) {
+        match self.find(Some(category.id)) {
+            0 => {
+                let pos = self.position_for(category.position);
+                self.add(category.name.clone(), Some(pos), Some(category));
+            }
+            pos => {
+                if matches!(self.unread[pos], Unread::Gone) {
+                    self.unread[pos] = Unread::Read;
+                }
+                self.labels[pos].rename(category.name.clone());
+                let moved = self.contents[pos].id.as_ref().map(|x| x.position) != Some(category.position);
+                let position = category.position;
+                self.contents[pos].id = Some(category);
+                if moved {
+                    self.move_to(pos, position);
+                }
+                self.flag = true;
+            }
+        }
+    }
+    /// Moves a category to where its new position puts it, keeping the cursor and the selection on it.
+    fn move_to(&mut self, pos: usize, position: i64) {
+        let (current, selected) = (self.current == pos, self.selected == pos);
+        let label = self.labels.remove(pos);
+        let unread = self.unread.remove(pos);
+        let channels = self.contents.remove(pos);
+        for index in [&mut self.current, &mut self.selected].iter_mut() {
+            if **index > pos {
+                **index -= 1;
+            }
+        }
+        let target = self.position_for(position);
+        for index in [&mut self.current, &mut self.selected].iter_mut() {
+            if **index >= target {
+                **index += 1;
+            }
+        }
+        self.labels.insert(target, label);
+        self.unread.insert(target, unread);
+        self.contents.insert(target, channels);
+        if current {
+            self.current = target;
+        }
+        if selected {
+            self.selected = target;
+        }
+    }
     pub fn get(&mut self) -> &mut Channels {
         &mut self.contents[self.selected]
     }
@@ -129,7 +199,7 @@ impl Categories {
         }
     }
     fn draw_real(&mut self, grid: &Grid, out: &mut Stdout) {
-        let start = self.beginning_pos(grid.height());
+        let start = render::scroll_start(self.current, self.labels.len(), grid.height());
         let sample = " ".graphemes(true).cycle();
         for i in start..start + grid.height() {
             let val: String = self
@@ -188,13 +258,11 @@ impl Categories {
             let _ = queue!(out, Print(crate::ansi::RESET.to_string(),));
         }
     }
-    fn beginning_pos(&self, height: usize) -> usize {
-        if height >= self.labels.len() || self.current <= height / 2 {
-            0
-        } else if self.current + height / 2 >= self.labels.len() {
-            self.labels.len() - height
-        } else {
-            self.current - height / 2
+    /// Moves the cursor to the item drawn on a row of the pane.
+    pub fn click(&mut self, row: usize, height: usize) {
+        if let Some(pos) = render::item_at(self.current, self.labels.len(), height, row) {
+            self.current = pos;
+            self.flag = true;
         }
     }
 }
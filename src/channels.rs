@@ -1,30 +1,56 @@
 use std::io::Stdout;
 
 use crossterm::{queue, style::Print};
-use serenity::model::channel::{Channel, GuildChannel};
+use serenity::model::{channel::{Channel, ChannelType, GuildChannel}, id::UserId};
 use unicode_segmentation::UnicodeSegmentation;
 
 use crate::colors::SimpleColor;
-use crate::{ansi, render::Grid, messages::Messages};
+use crate::{ansi, render::{self, Grid}, messages::Messages};
 
 use crate::servers::Unread;
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct ChannelLabel {
     name: String,
     color: SimpleColor,
+    #[serde(default)]
+    pub thread: bool,
+    #[serde(skip)]
+    pub connected: Vec<(UserId, String)>, // who's in the channel, if it's a voice channel
 }
 impl ChannelLabel {
     pub fn new(name: String) -> ChannelLabel {
         ChannelLabel {
             name,
             color: SimpleColor::new(),
+            thread: false,
+            connected: Vec::new(),
         }
     }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn rename(&mut self, name: String) {
+        self.name = name;
+    }
     pub fn to_string(&self) -> String {
-        self.name.clone() 
+        let name = if self.thread {
+            format!("↳ {}", self.name)
+        } else {
+            self.name.clone()
+        };
+        if self.connected.is_empty() {
+            name
+        } else {
+            format!("{} ({})", name, self.connected.len())
+        }
     }
 }
 
+/// Whether channels of this kind are shown in the channel tree.
+pub fn is_listed(kind: ChannelType) -> bool {
+    matches!(kind, ChannelType::Text | ChannelType::News | ChannelType::Store | ChannelType::Voice | ChannelType::Stage)
+}
+
 pub struct Channels {
     pub labels: Vec<ChannelLabel>,
     pub unread: Vec<Unread>,
@@ -74,9 +100,12 @@ impl Channels {
     }
     pub fn add(&mut self, name: String, pos: Option<usize>, id: Channel) {
         if let Some(pos) = pos {
-            if pos < self.current {
+            if pos <= self.current {
                 self.current += 1;
             }
+            if pos <= self.selected {
+                self.selected += 1;
+            }
             self.unread.insert(pos, Unread::Read);
             self.labels.insert(pos, ChannelLabel::new(name));
             self.contents.insert(pos, Messages::with_channel(id));
@@ -87,6 +116,15 @@ impl Channels {
         }
         self.flag = true;
     }
+    /// Adds a thread directly below its channel and any of the channel's other threads.
+    pub fn add_thread(&mut self, name: String, parent: usize, thread: GuildChannel) {
+        let mut pos = parent + 1;
+        while self.labels.get(pos).map(|x| x.thread).unwrap_or(false) {
+            pos += 1;
+        }
+        self.add(name, Some(pos), Channel::Guild(thread));
+        self.labels[pos].thread = true;
+    }
     fn remove(&mut self, pos: usize) {
         if pos <= self.current {
             self.current -= 1;
@@ -99,6 +137,52 @@ impl Channels {
         self.contents.remove(pos);
         self.flag = true;
     }
+    /// Takes a channel out along with its threads, keeping the cursor and selection on the channels around it.
+    pub fn take(&mut self, pos: usize) -> Vec<(ChannelLabel, Unread, Messages)> {
+        let mut end = pos + 1;
+        while self.labels.get(end).map(|x| x.thread).unwrap_or(false) {
+            end += 1;
+        }
+        let len = end - pos;
+        for index in [&mut self.current, &mut self.selected].iter_mut() {
+            if **index >= end {
+                **index -= len;
+            } else if **index >= pos {
+                **index = pos.saturating_sub(1);
+            }
+        }
+        self.flag = true;
+        self.labels
+            .drain(pos..end)
+            .zip(self.unread.drain(pos..end))
+            .zip(self.contents.drain(pos..end))
+            .map(|((label, unread), messages)| (label, unread, messages))
+            .collect()
+    }
+    /// Puts channels taken out by `take` back in at the given position.
+    pub fn put(&mut self, pos: usize, items: Vec<(ChannelLabel, Unread, Messages)>) {
+        let len = items.len();
+        for index in [&mut self.current, &mut self.selected].iter_mut() {
+            if **index >= pos {
+                **index += len;
+            }
+        }
+        for (i, (label, unread, messages)) in items.into_iter().enumerate() {
+            self.labels.insert(pos + i, label);
+            self.unread.insert(pos + i, unread);
+            self.contents.insert(pos + i, messages);
+        }
+        self.flag = true;
+    }
+    /// Where a channel with the given position belongs, going by the channels already here.
+    pub fn position_for(&self, position: i64) -> usize {
+        (1..self.labels.len())
+            .find(|&i| {
+                !self.labels[i].thread
+                    && matches!(self.contents[i].id(), Some(Channel::Guild(val)) if val.position > position)
+            })
+            .unwrap_or(self.labels.len())
+    }
     pub fn get(&mut self) -> &mut Messages {
         &mut self.contents[self.selected]
     }
@@ -120,6 +204,22 @@ impl Channels {
         self.flag = true;
         self.labels[self.current].color.toggle();
     }
+    /// The symbol shown before a channel's name, telling what kind of channel it is.
+    fn glyph(&self, pos: usize) -> &'static str {
+        if self.labels[pos].thread {
+            return "";
+        }
+        match self.contents[pos].id() {
+            Some(Channel::Guild(val)) => match val.kind {
+                ChannelType::News => "» ",
+                ChannelType::Store => "$ ",
+                ChannelType::Voice => "♪ ",
+                ChannelType::Stage => "◎ ",
+                _ => "# ",
+            },
+            _ => "",
+        }
+    }
     pub fn draw(&mut self, grid: &Grid, out: &mut Stdout) -> bool {
         if self.flag {
             self.draw_real(grid, out);
@@ -130,13 +230,13 @@ impl Channels {
         }
     }
     fn draw_real(&mut self, grid: &Grid, out: &mut Stdout) {
-        let start = self.beginning_pos(grid.height());
+        let start = render::scroll_start(self.current, self.labels.len(), grid.height());
         let sample = " ".graphemes(true).cycle();
         for i in start..start + grid.height() {
             let val: String = self
                 .labels
                 .get(i)
-                .map(|x| x.to_string())
+                .map(|x| self.glyph(i).to_string() + &x.to_string())
                 .unwrap_or(String::new())
                 .graphemes(true)
                 .chain(sample.clone())
@@ -189,13 +289,11 @@ impl Channels {
             let _ = queue!(out, Print(crate::ansi::RESET.to_string(),));
         }
     }
-    fn beginning_pos(&self, height: usize) -> usize {
-        if height >= self.labels.len() || self.current <= height / 2 {
-            0
-        } else if self.current + height / 2 >= self.labels.len() {
-            self.labels.len() - height
-        } else {
-            self.current - height / 2
+    /// Moves the cursor to the item drawn on a row of the pane.
+    pub fn click(&mut self, row: usize, height: usize) {
+        if let Some(pos) = render::item_at(self.current, self.labels.len(), height, row) {
+            self.current = pos;
+            self.flag = true;
         }
     }
 }
@@ -0,0 +1,57 @@
+use serenity::model::{channel::ReactionType, guild::Emoji};
+
+/// Shortcodes for the most commonly used unicode emoji.
+const SHORTCODES: &[(&str, &str)] = &[
+    ("thumbsup", "👍"),
+    ("+1", "👍"),
+    ("thumbsdown", "👎"),
+    ("-1", "👎"),
+    ("heart", "❤️"),
+    ("joy", "😂"),
+    ("smile", "😄"),
+    ("slight_smile", "🙂"),
+    ("cry", "😢"),
+    ("eyes", "👀"),
+    ("tada", "🎉"),
+    ("fire", "🔥"),
+    ("ok_hand", "👌"),
+    ("pray", "🙏"),
+    ("rocket", "🚀"),
+    ("100", "💯"),
+    ("thinking", "🤔"),
+    ("white_check_mark", "✅"),
+    ("x", "❌"),
+    ("wave", "👋"),
+    ("clap", "👏"),
+    ("skull", "💀"),
+];
+
+/// Turns user input into a reaction. `:name:` is looked up among the guild's emoji and
+/// then the built-in shortcodes; anything else is taken to be a unicode emoji.
+pub fn parse(s: &str, emojis: &[Emoji]) -> Option<ReactionType> {
+    let s = s.trim();
+    if s.is_empty() {
+        return None;
+    }
+    if let Some(name) = s.strip_prefix(':').and_then(|x| x.strip_suffix(':')) {
+        if let Some(val) = emojis.iter().find(|x| x.name == name) {
+            return Some(ReactionType::Custom {
+                animated: val.animated,
+                id: val.id,
+                name: Some(val.name.clone()),
+            });
+        }
+        return SHORTCODES
+            .iter()
+            .find(|x| x.0 == name)
+            .map(|x| ReactionType::Unicode(x.1.to_string()));
+    }
+    Some(ReactionType::Unicode(s.to_string()))
+}
+pub fn display(reaction: &ReactionType) -> String {
+    match reaction {
+        ReactionType::Custom { name, .. } => format!(":{}:", name.clone().unwrap_or_default()),
+        ReactionType::Unicode(val) => val.clone(),
+        _ => "?".to_string(),
+    }
+}
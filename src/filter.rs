@@ -0,0 +1,285 @@
+use std::io::Stdout;
+
+use crossterm::{queue, style::Print};
+use serenity::model::id::{ChannelId, GuildId, MessageId};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{ansi, format::format_time, index::Index, input::Context, keymap::{self, Action, Keymap}, messages::Messages, render::Grid, servers::Servers, textbox::Textbox};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Server(usize),
+    Category(usize, usize),
+    Channel(usize, usize, usize),
+    Message(ChannelId, Option<GuildId>, MessageId),
+    Action(keymap::Mode, Action),
+}
+/// What the filter is picking from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Channels,
+    Messages,
+    Bookmarks,
+    Help(keymap::Mode), // the bindings of the mode it was opened from
+}
+pub struct FilterResult {
+    pub score: i64,
+    pub target: Target,
+    pub path: String,
+}
+pub struct Filter {
+    pub query: Textbox,
+    pub results: Vec<FilterResult>,
+    pub current: usize,
+    pub flag: bool,
+    pub mode: Mode,
+}
+impl Filter {
+    pub fn new(length: usize) -> Filter {
+        Filter {
+            query: Textbox::new(length),
+            results: Vec::new(),
+            current: 0,
+            flag: true,
+            mode: Mode::Channels,
+        }
+    }
+    pub fn flag(&mut self) {
+        self.flag = true;
+        self.query.flag();
+    }
+    pub fn up(&mut self) {
+        if self.current > 0 {
+            self.current -= 1;
+            self.flag = true;
+        }
+    }
+    pub fn down(&mut self) {
+        if self.current + 1 < self.results.len() {
+            self.current += 1;
+            self.flag = true;
+        }
+    }
+    pub fn get(&self) -> Option<Target> {
+        self.results.get(self.current).map(|x| x.target)
+    }
+    /// Re-ranks every server, category and channel against the query.
+    pub fn update(&mut self, servers: &Servers) {
+        let query = self.query.text();
+        let mut results = Vec::new();
+        for (i, server) in servers.labels.iter().enumerate() {
+            let categories = &servers.contents[i];
+            push_result(&mut results, &query, Target::Server(i), server.name(), server.name().to_string());
+            for (j, category) in categories.labels.iter().enumerate() {
+                let path = format!("{}/{}", server.name(), category.name());
+                push_result(&mut results, &query, Target::Category(i, j), category.name(), path.clone());
+                let channels = &categories.contents[j];
+                for (k, channel) in channels.labels.iter().enumerate() {
+                    if let Messages::Nonexistent = channels.contents[k] {
+                        continue; // placeholder
+                    }
+                    let path = format!("{}/{}", path, channel.name());
+                    push_result(&mut results, &query, Target::Channel(i, j, k), channel.name(), path);
+                }
+            }
+        }
+        rank(&mut results);
+        self.results = results;
+        self.current = 0;
+        self.flag = true;
+    }
+    /// Re-ranks every bookmark against the query.
+    pub fn update_bookmarks(&mut self, servers: &Servers) {
+        let query = self.query.text();
+        let mut results = Vec::new();
+        for categories in &servers.contents {
+            for channels in &categories.contents {
+                for (label, messages) in channels.labels.iter().zip(channels.contents.iter()) {
+                    if let Messages::Loaded(val) = messages {
+                        for (name, id) in &val.bookmarks {
+                            let preview = val
+                                .labels
+                                .iter()
+                                .find(|x| x.id == *id)
+                                .and_then(|x| x.content.content.first().cloned())
+                                .unwrap_or_default();
+                            let path = format!("{} #{}: {}", name, label.name(), preview);
+                            let target = Target::Message(val.id.id(), categories.s_id, *id);
+                            push_result(&mut results, &query, target, name, path);
+                        }
+                    }
+                }
+            }
+        }
+        results.sort_by(|a, b| b.score.cmp(&a.score).then(a.path.cmp(&b.path)));
+        self.results = results;
+        self.current = 0;
+        self.flag = true;
+    }
+    /// Re-ranks the bindings active in the focused pane against the query, in keymap order.
+    pub fn update_help(&mut self, keymap: &Keymap, mode: keymap::Mode, context: &Context) {
+        let query = self.query.text();
+        let mut results = Vec::new();
+        for binding in keymap.active(mode, context) {
+            let name = binding.action.describe();
+            let path = format!("{:<12}{}", binding.key, name);
+            push_result(&mut results, &query, Target::Action(mode, binding.action), name, path);
+        }
+        results.sort_by_key(|x| std::cmp::Reverse(x.score));
+        self.results = results;
+        self.current = 0;
+        self.flag = true;
+    }
+    /// Lists every indexed message matching the query.
+    pub fn update_messages(&mut self, index: &Index) {
+        let query = self.query.text();
+        self.results = if query.trim().is_empty() {
+            Vec::new()
+        } else {
+            index
+                .search(&query)
+                .into_iter()
+                .map(|(id, x)| FilterResult {
+                    score: 0,
+                    target: Target::Message(x.channel, x.guild, id),
+                    path: format!("#{} {} {}: {}", x.channel_name, format_time(x.time), x.author, x.preview),
+                })
+                .collect()
+        };
+        self.current = 0;
+        self.flag = true;
+    }
+    pub fn draw(&mut self, grid: &Grid, out: &mut Stdout) -> bool {
+        if self.flag {
+            self.draw_real(grid, out);
+            self.flag = false;
+            true
+        } else {
+            false
+        }
+    }
+    fn draw_real(&mut self, grid: &Grid, out: &mut Stdout) {
+        let sample = " ".graphemes(true).cycle();
+        let start = if self.current < grid.height() {
+            0
+        } else {
+            self.current + 1 - grid.height()
+        };
+        for i in start..start + grid.height() {
+            let val: String = self
+                .results
+                .get(i)
+                .map(|x| x.path.clone())
+                .unwrap_or_default()
+                .graphemes(true)
+                .chain(sample.clone())
+                .take(grid.len_messages())
+                .collect();
+            let true_pos = i - start + grid.start_y;
+            let _ = queue!(
+                out,
+                crossterm::cursor::MoveTo(grid.border_3 as u16, true_pos as u16,)
+            );
+            if i == self.current {
+                let _ = queue!(out, Print(ansi::BACKGROUND_LIGHT_GREY.to_string()));
+            }
+            let _ = queue!(out, Print(val));
+            let _ = queue!(out, Print(crate::ansi::RESET.to_string()));
+        }
+    }
+}
+/// Puts the best matches first, breaking ties in favour of the shallower, shorter path.
+fn rank(results: &mut [FilterResult]) {
+    results.sort_by(|a, b| b.score.cmp(&a.score).then(a.path.len().cmp(&b.path.len())));
+}
+fn push_result(results: &mut Vec<FilterResult>, query: &str, target: Target, name: &str, path: String) {
+    // matches on the name itself are preferred over matches that span the path
+    let score = score(query, name).map(|x| x + 10).max(score(query, &path));
+    if let Some(score) = score {
+        results.push(FilterResult { score, target, path });
+    }
+}
+/// Scores how well the query fuzzy-matches the target, or returns None if it doesn't match.
+/// Every character of the query has to appear in order; consecutive characters and
+/// characters at the start of a word score higher, and gaps score lower.
+pub fn score(query: &str, target: &str) -> Option<i64> {
+    let target: Vec<char> = target.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut last: Option<usize> = None;
+    for c in query.to_lowercase().chars().filter(|x| !x.is_whitespace()) {
+        let found = target[pos..].iter().position(|x| *x == c)? + pos;
+        score += 1;
+        if found > 0 && last == Some(found - 1) {
+            score += 5;
+        } else if last.is_some() {
+            score -= (found - pos) as i64;
+        }
+        if found == 0 || !target[found - 1].is_alphanumeric() {
+            score += 3;
+        }
+        last = Some(found);
+        pos = found + 1;
+    }
+    Some(score)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranked(query: &str, paths: &[&str]) -> Vec<String> {
+        let mut results = Vec::new();
+        for (i, path) in paths.iter().enumerate() {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            push_result(&mut results, query, Target::Server(i), name, path.to_string());
+        }
+        rank(&mut results);
+        results.into_iter().map(|x| x.path).collect()
+    }
+
+    #[test]
+    fn characters_have_to_appear_in_order() {
+        assert!(score("gnrl", "general").is_some());
+        assert!(score("lrng", "general").is_none());
+        assert!(score("x", "").is_none());
+    }
+
+    #[test]
+    fn empty_query_matches_everything_equally() {
+        assert_eq!(score("", "general"), Some(0));
+        assert_eq!(score("  ", "off-topic"), Some(0));
+    }
+
+    #[test]
+    fn case_is_ignored() {
+        assert_eq!(score("GEN", "general"), score("gen", "General"));
+    }
+
+    #[test]
+    fn consecutive_characters_beat_scattered_ones() {
+        assert!(score("gen", "general") > score("gen", "go-eat-now"));
+    }
+
+    #[test]
+    fn word_starts_beat_the_middle_of_words() {
+        assert!(score("top", "off-topic") > score("top", "stopwatch"));
+    }
+
+    #[test]
+    fn matches_on_the_name_beat_matches_across_the_path() {
+        assert_eq!(ranked("dev", &["dev/general", "rust/dev"]), vec!["rust/dev", "dev/general"]);
+    }
+
+    #[test]
+    fn ties_go_to_the_shorter_path() {
+        assert_eq!(
+            ranked("general", &["server/category/general", "server/general", "general"]),
+            vec!["general", "server/general", "server/category/general"]
+        );
+    }
+
+    #[test]
+    fn non_matches_are_left_out() {
+        assert_eq!(ranked("xyz", &["general", "random"]), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,157 @@
+use crate::{ansi, markdown::Span};
+
+/// How to pick out the parts of a language worth coloring.
+struct Language {
+    names: &'static [&'static str],
+    extension: &'static str,
+    keywords: &'static [&'static str],
+    comment: &'static str,
+    quotes: &'static str,
+    variables: bool, // whether `$name` is a variable, as in shell
+}
+const LANGUAGES: &[Language] = &[
+    Language {
+        names: &["rust", "rs"],
+        extension: "rs",
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false", "fn", "for", "if", "impl",
+            "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+            "true", "type", "unsafe", "use", "where", "while",
+        ],
+        comment: "//",
+        quotes: "\"",
+        variables: false,
+    },
+    Language {
+        names: &["sh", "bash", "shell", "zsh", "console"],
+        extension: "sh",
+        keywords: &[
+            "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case", "esac", "in", "function", "return", "local",
+            "export", "echo", "cd", "exit", "set", "unset", "source",
+        ],
+        comment: "#",
+        quotes: "\"'",
+        variables: true,
+    },
+    Language {
+        names: &["python", "py"],
+        extension: "py",
+        keywords: &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif", "else", "except", "False", "finally",
+            "for", "from", "global", "if", "import", "in", "is", "lambda", "None", "not", "or", "pass", "raise", "return", "True", "try",
+            "while", "with", "yield",
+        ],
+        comment: "#",
+        quotes: "\"'",
+        variables: false,
+    },
+    Language {
+        names: &["javascript", "js", "typescript", "ts", "jsx", "tsx"],
+        extension: "js",
+        keywords: &[
+            "async", "await", "break", "case", "catch", "class", "const", "continue", "default", "delete", "else", "export", "extends", "false",
+            "finally", "for", "from", "function", "if", "import", "in", "instanceof", "let", "new", "null", "return", "switch", "this",
+            "throw", "true", "try", "typeof", "undefined", "var", "while", "yield",
+        ],
+        comment: "//",
+        quotes: "\"'`",
+        variables: false,
+    },
+    Language {
+        names: &["c", "h", "cpp", "c++", "hpp", "java", "cs", "go"],
+        extension: "c",
+        keywords: &[
+            "auto", "break", "case", "char", "class", "const", "continue", "default", "do", "double", "else", "enum", "extern", "false",
+            "float", "for", "func", "if", "import", "int", "long", "new", "nullptr", "package", "private", "public", "return", "short",
+            "signed", "sizeof", "static", "struct", "switch", "this", "true", "typedef", "union", "unsigned", "void", "while",
+        ],
+        comment: "//",
+        quotes: "\"'",
+        variables: false,
+    },
+    Language {
+        names: &["toml", "ini", "yaml", "yml"],
+        extension: "toml",
+        keywords: &["true", "false"],
+        comment: "#",
+        quotes: "\"'",
+        variables: false,
+    },
+];
+fn language(tag: &str) -> Option<&'static Language> {
+    let tag = tag.trim().to_lowercase();
+    LANGUAGES.iter().find(|x| x.names.contains(&tag.as_str()))
+}
+/// The file extension to save a code block in the given language with.
+pub fn extension(tag: &str) -> &'static str {
+    language(tag).map(|x| x.extension).unwrap_or("txt")
+}
+/// Colors one line of code. Lines in unknown languages are left plain.
+pub fn highlight(line: &str, tag: &str) -> Vec<Span> {
+    let lang = match language(tag) {
+        Some(val) => val,
+        None => return vec![Span::new(line, "")],
+    };
+    let mut spans: Vec<Span> = Vec::new();
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        let len = if rest.starts_with(lang.comment) {
+            push(&mut spans, rest, ansi::LOW_INTENSITY.to_string() + ansi::ITALIC);
+            rest.len()
+        } else if lang.quotes.contains(c) {
+            let len = string_len(rest, c);
+            push(&mut spans, &rest[..len], ansi::GREEN);
+            len
+        } else if c.is_ascii_digit() {
+            let len = rest.find(|x: char| !x.is_ascii_alphanumeric() && x != '.' && x != '_').unwrap_or(rest.len());
+            push(&mut spans, &rest[..len], ansi::YELLOW);
+            len
+        } else if c.is_alphabetic() || c == '_' || (c == '$' && lang.variables) {
+            let len = rest[c.len_utf8()..]
+                .find(|x: char| !x.is_alphanumeric() && x != '_')
+                .map(|x| x + c.len_utf8())
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            let style = if c == '$' {
+                ansi::CYAN
+            } else if lang.keywords.contains(&word) {
+                ansi::MAGENTA
+            } else if rest[len..].starts_with('(') || rest[len..].starts_with('!') {
+                ansi::BLUE // function calls and macros
+            } else if c.is_uppercase() {
+                ansi::CYAN
+            } else {
+                ""
+            };
+            push(&mut spans, word, style);
+            len
+        } else {
+            push(&mut spans, &rest[..c.len_utf8()], "");
+            c.len_utf8()
+        };
+        rest = &rest[len..];
+    }
+    spans
+}
+/// The length of the string starting at the beginning of `s`, including its quotes.
+fn string_len(s: &str, quote: char) -> usize {
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == quote {
+            return i + c.len_utf8();
+        }
+    }
+    s.len()
+}
+/// Adds text to the last span if it's in the same style.
+fn push(spans: &mut Vec<Span>, text: &str, style: impl Into<String>) {
+    let style = style.into();
+    match spans.last_mut() {
+        Some(val) if val.style == style => val.text.push_str(text),
+        _ => spans.push(Span::new(text, style)),
+    }
+}
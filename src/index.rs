@@ -0,0 +1,235 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Local, NaiveDate};
+use serenity::model::id::{ChannelId, GuildId, MessageId};
+
+use crate::{message::LoadedMessage, messages::Messages, servers::Servers};
+
+pub struct Entry {
+    pub channel: ChannelId,
+    pub guild: Option<GuildId>,
+    pub channel_name: String,
+    pub author: String,
+    pub time: DateTime<Local>,
+    pub attachment: bool,
+    pub preview: String,
+    words: Vec<String>,
+}
+/// An inverted index over every loaded message, used for searching across channels.
+pub struct Index {
+    words: HashMap<String, HashSet<MessageId>>,
+    entries: HashMap<MessageId, Entry>,
+}
+impl Index {
+    pub fn new() -> Index {
+        Index {
+            words: HashMap::new(),
+            entries: HashMap::new(),
+        }
+    }
+    /// Indexes every message that's been added or edited since the last update,
+    /// and drops the ones that have been deleted.
+    pub fn update(&mut self, servers: &mut Servers) {
+        for categories in servers.contents.iter_mut() {
+            let guild = categories.s_id;
+            for channels in categories.contents.iter_mut() {
+                for (label, messages) in channels.labels.iter().zip(channels.contents.iter_mut()) {
+                    if let Messages::Loaded(val) = messages {
+                        if val.unindexed.is_empty() {
+                            continue;
+                        }
+                        let positions: HashMap<MessageId, usize> = val.labels.iter().enumerate().map(|(i, x)| (x.id, i)).collect();
+                        let channel = val.id.id();
+                        let labels = &val.labels;
+                        for id in val.unindexed.drain(..) {
+                            let msg = positions.get(&id).map(|x| &labels[*x]);
+                            self.refresh(id, msg, channel, guild, label.name());
+                        }
+                    }
+                }
+            }
+        }
+    }
+    /// Indexes a message again, or drops it if it's been deleted or isn't loaded anymore.
+    fn refresh(&mut self, id: MessageId, msg: Option<&LoadedMessage>, channel: ChannelId, guild: Option<GuildId>, channel_name: &str) {
+        match msg {
+            Some(msg) if !msg.deleted => self.insert(msg, channel, guild, channel_name),
+            _ => self.remove(id),
+        }
+    }
+    /// Indexes the newest revision of a message, replacing whatever was indexed for it before.
+    fn insert(&mut self, msg: &LoadedMessage, channel: ChannelId, guild: Option<GuildId>, channel_name: &str) {
+        self.remove(msg.id);
+        let content = msg.latest();
+        let words: Vec<String> = content
+            .content
+            .iter()
+            .chain(content.attachments.iter())
+            .flat_map(|x| tokenize(x))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        for word in &words {
+            self.words.entry(word.clone()).or_default().insert(msg.id);
+        }
+        self.entries.insert(msg.id, Entry {
+            channel,
+            guild,
+            channel_name: channel_name.to_string(),
+            author: msg.username.clone(),
+            time: msg.first_time,
+            attachment: !content.attachments.is_empty(),
+            preview: content.content.first().cloned().unwrap_or_default(),
+            words,
+        });
+    }
+    fn remove(&mut self, id: MessageId) {
+        if let Some(entry) = self.entries.remove(&id) {
+            for word in entry.words {
+                if let Some(set) = self.words.get_mut(&word) {
+                    set.remove(&id);
+                }
+            }
+        }
+    }
+    /// Finds every message matching the query, newest first.
+    /// Besides plain words, the query supports `from:user`, `in:channel`,
+    /// `before:yyyy-mm-dd`, `after:yyyy-mm-dd` and `has:attachment`.
+    pub fn search(&self, query: &str) -> Vec<(MessageId, &Entry)> {
+        let query = Query::parse(query);
+        let mut candidates: Option<HashSet<MessageId>> = None;
+        for word in &query.words {
+            let found = self.words.get(word).cloned().unwrap_or_default();
+            candidates = Some(match candidates {
+                Some(val) => val.intersection(&found).copied().collect(),
+                None => found,
+            });
+        }
+        let mut result: Vec<(MessageId, &Entry)> = match candidates {
+            Some(ids) => ids.into_iter().filter_map(|x| self.entries.get(&x).map(|y| (x, y))).collect(),
+            None => self.entries.iter().map(|(x, y)| (*x, y)).collect(),
+        };
+        result.retain(|(_, x)| query.accepts(x));
+        result.sort_by_key(|x| std::cmp::Reverse(x.1.time));
+        result
+    }
+}
+#[derive(Default)]
+struct Query {
+    words: Vec<String>,
+    from: Option<String>,
+    channel: Option<String>,
+    before: Option<NaiveDate>,
+    after: Option<NaiveDate>,
+    attachment: bool,
+}
+impl Query {
+    fn parse(s: &str) -> Query {
+        let mut query = Query::default();
+        for term in s.split_whitespace() {
+            let lower = term.to_lowercase();
+            if let Some(val) = lower.strip_prefix("from:") {
+                query.from = Some(val.to_string());
+            } else if let Some(val) = lower.strip_prefix("in:") {
+                query.channel = Some(val.trim_start_matches('#').to_string());
+            } else if let Some(val) = lower.strip_prefix("before:") {
+                query.before = NaiveDate::parse_from_str(val, "%Y-%m-%d").ok();
+            } else if let Some(val) = lower.strip_prefix("after:") {
+                query.after = NaiveDate::parse_from_str(val, "%Y-%m-%d").ok();
+            } else if lower == "has:attachment" || lower == "has:file" {
+                query.attachment = true;
+            } else {
+                query.words.append(&mut tokenize(term));
+            }
+        }
+        query
+    }
+    fn accepts(&self, entry: &Entry) -> bool {
+        let date = entry.time.date().naive_local();
+        self.from.as_ref().map(|x| entry.author.to_lowercase().contains(x)).unwrap_or(true)
+            && self.channel.as_ref().map(|x| entry.channel_name.to_lowercase().contains(x)).unwrap_or(true)
+            && self.before.map(|x| date < x).unwrap_or(true)
+            && self.after.map(|x| date > x).unwrap_or(true)
+            && (!self.attachment || entry.attachment)
+    }
+}
+fn tokenize(s: &str) -> Vec<String> {
+    s.split(|x: char| !x.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_lowercase())
+        .collect()
+}
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serenity::model::id::UserId;
+
+    use super::*;
+
+    const CHANNEL: ChannelId = ChannelId(1);
+
+    fn message(id: u64, author: &str, text: &str, date: (i32, u32, u32)) -> LoadedMessage {
+        let time = Local.ymd(date.0, date.1, date.2).and_hms(12, 0, 0);
+        LoadedMessage::from_content(UserId(id), vec![text.to_string()], time, MessageId(id), author.to_string())
+    }
+    fn found(index: &Index, query: &str) -> Vec<u64> {
+        index.search(query).into_iter().map(|x| x.0 .0).collect()
+    }
+    fn index(messages: &[LoadedMessage]) -> Index {
+        let mut index = Index::new();
+        for msg in messages {
+            index.refresh(msg.id, Some(msg), CHANNEL, None, "general");
+        }
+        index
+    }
+
+    #[test]
+    fn every_word_has_to_match() {
+        let index = index(&[message(1, "ann", "Hello there, world", (2021, 1, 1)), message(2, "bob", "hello again", (2021, 1, 2))]);
+        assert_eq!(found(&index, "hello"), vec![2, 1]);
+        assert_eq!(found(&index, "HELLO world"), vec![1]);
+        assert_eq!(found(&index, "hello nobody"), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn filters_narrow_the_results() {
+        let index = index(&[message(1, "ann", "hello", (2021, 1, 1)), message(2, "bob", "hello", (2021, 3, 1))]);
+        assert_eq!(found(&index, "hello from:BOB"), vec![2]);
+        assert_eq!(found(&index, "before:2021-02-01"), vec![1]);
+        assert_eq!(found(&index, "after:2021-02-01"), vec![2]);
+        assert_eq!(found(&index, "in:#general"), vec![2, 1]);
+        assert_eq!(found(&index, "in:random"), Vec::<u64>::new());
+        assert_eq!(found(&index, "has:attachment"), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn edits_replace_the_old_words() {
+        let mut msg = message(1, "ann", "old text", (2021, 1, 1));
+        let mut index = index(std::slice::from_ref(&msg));
+        msg.push_content(vec!["new text".to_string()], Local::now());
+        // the newest revision is indexed even while an older one is shown
+        msg.older();
+        index.refresh(msg.id, Some(&msg), CHANNEL, None, "general");
+        assert_eq!(found(&index, "old"), Vec::<u64>::new());
+        assert_eq!(found(&index, "new"), vec![1]);
+        assert_eq!(index.search("text")[0].1.preview, "new text");
+    }
+
+    #[test]
+    fn deleted_messages_are_dropped() {
+        let mut msg = message(1, "ann", "gone soon", (2021, 1, 1));
+        let mut index = index(std::slice::from_ref(&msg));
+        msg.deleted = true;
+        index.refresh(msg.id, Some(&msg), CHANNEL, None, "general");
+        assert_eq!(found(&index, "gone"), Vec::<u64>::new());
+        assert!(index.words.values().all(|x| x.is_empty()));
+    }
+
+    #[test]
+    fn unloaded_messages_are_dropped() {
+        let msg = message(1, "ann", "hello", (2021, 1, 1));
+        let mut index = index(&[msg]);
+        index.refresh(MessageId(1), None, CHANNEL, None, "general");
+        assert_eq!(found(&index, "hello"), Vec::<u64>::new());
+    }
+}
@@ -1,6 +1,11 @@
 mod categories;
 mod channels;
+mod edit;
+mod filter;
 mod messages;
+mod motion;
+mod mouse;
+mod react;
 mod servers;
 
 use std::{collections::HashMap, io::{stdout, Stdout}, sync::{
@@ -8,24 +13,32 @@ use std::{collections::HashMap, io::{stdout, Stdout}, sync::{
         Arc,
     }, thread::{spawn, JoinHandle}, time::Duration};
 
-use crate::{block_on::block_on, file::{ExtConfig, get_str}, render::Grid, message::UserDict, save::{Autosave, ParserSave, Return, load, save}, servers::Servers, task::{Control, Product, Task}, textbox::Textbox};
+use crate::{ansi, block_on::block_on, colors::Color, emoji, file::{ExtConfig, get_str}, filter::{Filter, Mode}, index::Index, keymap::{self, Action, Keymap}, render::{Grid, Layout}, message::{UserDict, UserInfo}, messages::Messages, permissions::Membership, save::{Autosave, ParserSave, Return, load, save}, servers::Servers, task::{Control, Product, Task}, textbox::Textbox};
+use motion::Motion;
 use crossterm::{
+    cursor::MoveTo,
     event::{read, Event, KeyCode, KeyEvent},
     execute, queue,
+    style::Print,
     terminal::{Clear, ClearType},
 };
+use unicode_segmentation::UnicodeSegmentation;
 use serde_json::json;
 use serenity::{
     client,
     http::{GuildPagination, Http},
     model::{
-        channel::{Channel, ChannelType, GuildChannel, Message, PrivateChannel},
-        guild::GuildInfo,
-        id::{ChannelId, GuildId},
+        channel::{Channel, ChannelType, GuildChannel, Message, PrivateChannel, Reaction, ReactionType},
+        event::MessageUpdateEvent,
+        guild::{Emoji, GuildInfo},
+        id::{ChannelId, GuildId, MessageId, RoleId, UserId},
+        voice::VoiceState,
+        Permissions,
     },
     Client,
 };
 pub const REQUEST_LEN:usize = 50;
+const GUILD_PAGE: u64 = 100; // the most servers Discord lists at once
 
 fn grab(out: Sender<Event>) {
     spawn(move || loop {
@@ -41,7 +54,13 @@ pub enum State {
     Filter,
     Quit,
     Visual,
+    Search,
+    Edit,
+    React,
+    Date,
+    Bookmark,
 }
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum Context {
     Server,
     Category,
@@ -50,6 +69,16 @@ pub enum Context {
 }
 pub enum Response {
     Message(Message),
+    Edit(MessageUpdateEvent),
+    Delete(ChannelId, Vec<MessageId>, Option<GuildId>),
+    Reaction(Reaction, bool), // whether it was added or removed
+    Thread(GuildChannel),
+    Voice(VoiceState),
+    Guild(GuildId, String, Option<Vec<GuildChannel>>), // every channel, or None if only the server changed
+    GuildDelete(GuildId, bool), // whether the guild is only unavailable
+    ChannelUpdate(GuildChannel),
+    ChannelDelete(GuildChannel),
+    Ready, // a new session started, so events may have been missed
 }
 pub struct ParserIO {
     pub input_server: Receiver<Response>,
@@ -66,6 +95,22 @@ pub struct ParserInternal {
     pub user_dict: UserDict,
     pub file_options: ExtConfig,
     pub autosave: Autosave,
+    pub me: UserId,
+    pub index: Index,
+    pub edit: Option<edit::Edit>,
+    pub reply: Option<Reply>,
+    pub attachments: Vec<String>, // paths of files to upload with the next message
+    pub recent_emoji: Vec<ReactionType>, // most recent first
+    pub memberships: HashMap<GuildId, Membership>,
+    pub emojis: HashMap<GuildId, Vec<Emoji>>, // each server's custom emojis, for the reaction picker
+    pub count: usize, // the count typed before a motion, or 0
+    pub keymap: Keymap,
+}
+/// The message that the message box is replying to.
+pub struct Reply {
+    pub channel: ChannelId,
+    pub message: MessageId,
+    pub preview: String,
 }
 pub struct Parser {
     pub io: ParserIO,
@@ -73,6 +118,8 @@ pub struct Parser {
     pub servers: Servers,
     pub message_box: Textbox,
     pub temp_box: Textbox,
+    pub prompt_box: Textbox,
+    pub filter: Filter,
 }
 impl Parser {
     pub fn new(input_server: Receiver<Response>, client: Client, tasks: Sender<Task>, controller: Sender<Control>, products: Receiver<Product>) -> Parser {
@@ -90,8 +137,15 @@ impl Parser {
         let (max_x, max_y) = crossterm::terminal::size().expect("Cannot read size of terminal");
         let max_x = max_x as usize;
         let max_y = max_y as usize;
-        let grid = Grid::new(max_x, max_y);
-        Parser {
+        let grid = Grid::new(max_x, max_y, save.layout);
+        // without a connection, the user saved last time is still right
+        let saved = save.user_dict.me;
+        let me = current_user(&client)
+            .or_else(|why| if saved.0 == 0 {Err(why)} else {Ok(saved)})
+            .unwrap_or_else(|why| panic!("Couldn't look up the logged in user: {}", why));
+        let mut user_dict = save.user_dict;
+        user_dict.me = me;
+        let mut parser = Parser {
             io: ParserIO {
                 input_server,
                 client,
@@ -104,20 +158,37 @@ impl Parser {
             int: ParserInternal {
                 state: State::None,
                 grid,
-                user_dict: save.user_dict,
+                user_dict,
                 autosave: Autosave::new(),
                 file_options: ExtConfig::new(),
+                me,
+                index: Index::new(),
+                edit: None,
+                reply: None,
+                attachments: Vec::new(),
+                recent_emoji: Vec::new(),
+                memberships: HashMap::new(),
+                emojis: HashMap::new(),
+                count: 0,
+                keymap: Keymap::new(),
             },
             servers: save.servers.reload(),
             message_box: Textbox::new(max_x),
             temp_box: Textbox::new(max_x),
-        }
+            prompt_box: Textbox::new(max_x),
+            filter: Filter::new(max_x),
+        };
+        parser.network_update_saved();
+        parser
     }
     pub fn complete_new(input_server: Receiver<Response>, client: Client, tasks: Sender<Task>, controller: Sender<Control>, products: Receiver<Product>) -> Parser {
         let (temp, input_user) = channel ();
         grab(temp);
         let (max_x, max_y) = crossterm::terminal::size().expect("Cannot read size of terminal");
-        let grid = Grid::new(max_x as usize, max_y as usize);
+        let grid = Grid::new(max_x as usize, max_y as usize, Layout::default());
+        let me = current_user(&client).unwrap_or_else(|why| panic!("Couldn't look up the logged in user: {}", why));
+        let mut user_dict = UserDict::new();
+        user_dict.me = me;
         let mut parser = Parser {
             io: ParserIO {
                 input_server,
@@ -131,13 +202,25 @@ impl Parser {
             int: ParserInternal {
                 state: State::None,
                 grid,
-                user_dict: UserDict::new(),
+                user_dict,
                 autosave: Autosave::new(),
                 file_options: ExtConfig::new(),
+                me,
+                index: Index::new(),
+                edit: None,
+                reply: None,
+                attachments: Vec::new(),
+                recent_emoji: Vec::new(),
+                memberships: HashMap::new(),
+                emojis: HashMap::new(),
+                count: 0,
+                keymap: Keymap::new(),
             },
             servers: Servers::new(),
             message_box: Textbox::new(max_x as usize),
             temp_box: Textbox::new(max_x as usize),
+            prompt_box: Textbox::new(max_x as usize),
+            filter: Filter::new(max_x as usize),
         };
         parser.network_update_first();
         parser
@@ -162,9 +245,13 @@ impl Parser {
             for line in responses {
                 self.handle_response(line);
             }
+            self.check_read();
             if self.int.state != State::Quit {
                 self.draw();
             }
+            if let Some(why) = self.int.keymap.error.take() {
+                self.notice(why);
+            }
             self.save_state();
         }
         self.end_state();
@@ -175,13 +262,73 @@ impl Parser {
             Response::Message(message) => {
                 self.add_message(message);
             }
+            Response::Edit(event) => {
+                self.edit_message(event);
+            }
+            Response::Delete(ch, messages, guild) => {
+                if let Some(res) = self.servers.find_channel(ch, guild) {
+                    res.receive_delete(&self.io.tasks, ch, messages, guild);
+                }
+            }
+            Response::Reaction(reaction, added) => {
+                if let Some(res) = self.servers.find_channel(reaction.channel_id, reaction.guild_id) {
+                    res.receive_reaction(&self.io.tasks, reaction, added, self.int.me);
+                }
+            }
+            Response::Thread(thread) => {
+                self.int.user_dict.channels.insert(thread.id, thread.name.clone());
+                self.servers.add_thread(thread);
+            }
+            Response::Voice(state) => {
+                let name = match (&state.member, self.int.user_dict.contents.get(&state.user_id)) {
+                    (Some(member), _) => member.user.name.clone(),
+                    (None, Some(info)) => info.name.clone(),
+                    (None, None) => state.user_id.0.to_string(),
+                };
+                self.servers.voice_update(&state, name);
+                self.message_box.flag();
+            }
+            Response::Guild(guild, name, Some(channels)) => {
+                for channel in &channels {
+                    self.int.user_dict.channels.insert(channel.id, channel.name.clone());
+                }
+                self.servers.sync_guild(guild, name, channels);
+                self.tree_changed();
+            }
+            Response::Guild(guild, name, None) => {
+                self.servers.update_guild(guild, name, Vec::new());
+                self.tree_changed();
+            }
+            Response::GuildDelete(guild, unavailable) => {
+                self.servers.remove_guild(guild, unavailable);
+                self.tree_changed();
+            }
+            Response::ChannelUpdate(channel) => {
+                self.int.user_dict.channels.insert(channel.id, channel.name.clone());
+                self.servers.update_channel(channel);
+                self.tree_changed();
+            }
+            Response::Ready => {
+                self.servers.stale();
+            }
+            Response::ChannelDelete(channel) => {
+                self.servers.delete_channel(channel);
+                self.tree_changed();
+            }
         }
     }
+    /// Redraws everything after servers, categories or channels were added, moved or removed.
+    fn tree_changed(&mut self) {
+        if self.int.state == State::Filter && self.filter.mode == Mode::Channels {
+            self.filter.update(&self.servers);
+        }
+        self.reset_all();
+    }
     pub fn handle_event(&mut self, e: Event) -> bool {
         match e {
             Event::Key(key) => match self.int.state {
                 State::None => self.parse_none(key),
-                State::Filter => {}
+                State::Filter => self.parse_filter(key),
                 State::Message => self.parse_message(key),
                 State::Quit => {
                     if self.parse_quit(key) {
@@ -189,8 +336,13 @@ impl Parser {
                     }
                 }
                 State::Visual => self.parse_visual(key),
+                State::Search => self.parse_search(key),
+                State::Edit => self.parse_edit(key),
+                State::React => self.parse_react(key),
+                State::Date => self.parse_date(key),
+                State::Bookmark => self.parse_bookmark(key),
             },
-            Event::Mouse(_) => todo!(),
+            Event::Mouse(mouse) => self.parse_mouse(mouse),
             Event::Resize(length, height) => {
                 self.int.grid.update(
                     self.message_box.lines().min(self.int.grid.max_box_len),
@@ -205,19 +357,95 @@ impl Parser {
     /// Returns whether a save can happen. 
     pub fn handle_product(&mut self, p: Product) -> bool {
         match p {
-            Product::MessagesBefore(content, channel) => {
-                let msg = self.servers.find_channel(channel.id(), if let Channel::Guild(v) = &channel {Some(v.guild_id)} else {None});
-                msg.assume_loaded().receive_update(&mut self.int.user_dict, content);
+            Product::MessagesBefore(Ok(content), channel) => {
+                if let Some(msg) = self.servers.find_channel(channel.id(), if let Channel::Guild(v) = &channel {Some(v.guild_id)} else {None}) {
+                    msg.assume_loaded().receive_update(&mut self.int.user_dict, content);
+                    msg.assume_loaded().continue_search(&self.io.tasks);
+                }
             },
-            Product::MessagesAfter(content, channel) => {
-                let msg = self.servers.find_channel(channel.id(), if let Channel::Guild(v) = &channel {Some(v.guild_id)} else {None});
-                msg.assume_loaded().receive_update_after(&mut self.int.user_dict, content);
+            Product::MessagesBefore(Err(why), channel) => {
+                if let Some(msg) = self.servers.find_channel(channel.id(), if let Channel::Guild(v) = &channel {Some(v.guild_id)} else {None}) {
+                    msg.assume_loaded().update_failed();
+                }
+                self.notice(format!("Couldn't load older messages: {}", why));
+            },
+            Product::MessagesAfter(Ok(content), channel, anchor) => {
+                if let Some(msg) = self.servers.find_channel(channel.id(), if let Channel::Guild(v) = &channel {Some(v.guild_id)} else {None}) {
+                    msg.assume_loaded().receive_update_after(&mut self.int.user_dict, content, anchor);
+                }
+            },
+            Product::MessagesAfter(Err(why), channel, anchor) => {
+                if let Some(msg) = self.servers.find_channel(channel.id(), if let Channel::Guild(v) = &channel {Some(v.guild_id)} else {None}) {
+                    msg.assume_loaded().update_after_failed(anchor);
+                }
+                self.notice(format!("Couldn't load newer messages: {}", why));
             },
             Product::MessagesNew(content, channel) => {
-                    let msg = self.servers.find_channel(channel.id(), if let Channel::Guild(v) = &channel {Some(v.guild_id)} else {None});;
+                    let guild = if let Channel::Guild(v) = &channel {Some(v.guild_id)} else {None};
+                    let msg = match self.servers.find_channel(channel.id(), guild) {
+                        Some(val) => val,
+                        None => return false,
+                    };
                     let more_messages: bool = content.len() >= REQUEST_LEN;
                     msg.receive_new(&mut self.int.user_dict, &mut self.io.tasks, content, more_messages);
+                    // archived threads are only looked up once their channel is opened
+                    if let (Some(_), Ok(pos)) = (guild, self.servers.find_pos(channel.id(), guild)) {
+                        if !self.servers.is_thread(pos) {
+                            self.io.tasks.send(Task::GetArchivedThreads(channel.id())).expect("Could not send!");
+                        }
+                    }
+            },
+            Product::History(Ok(content), channel, done) => {
+                if let Some(msg) = self.servers.find_channel(channel.id(), if let Channel::Guild(v) = &channel {Some(v.guild_id)} else {None}) {
+                    msg.assume_loaded().receive_history(&mut self.int.user_dict, content, done);
+                    msg.assume_loaded().continue_search(&self.io.tasks);
+                }
             },
+            Product::History(Err(why), channel, _) => {
+                if let Some(msg) = self.servers.find_channel(channel.id(), if let Channel::Guild(v) = &channel {Some(v.guild_id)} else {None}) {
+                    msg.assume_loaded().history_failed();
+                }
+                self.notice(format!("Couldn't load older messages: {}", why));
+            },
+            Product::Threads(Ok(threads)) => {
+                for thread in threads {
+                    self.int.user_dict.channels.insert(thread.id, thread.name.clone());
+                    self.servers.add_thread(thread);
+                }
+            }
+            Product::Threads(Err(why)) => self.notice(format!("Couldn't load threads: {}", why)),
+            Product::GuildInfo(guild, threads, roles, mine, owner, emojis) => {
+                self.int.emojis.insert(guild, emojis);
+                match threads {
+                    Ok(threads) => {
+                        for thread in threads {
+                            self.int.user_dict.channels.insert(thread.id, thread.name.clone());
+                            self.servers.add_thread(thread);
+                        }
+                    }
+                    Err(why) => self.notice(format!("Couldn't load threads: {}", why)),
+                }
+                if let Some(mine) = mine {
+                    self.int.memberships.insert(guild, Membership::new(&roles, &mine, RoleId(guild.0), owner));
+                }
+                for role in roles {
+                    self.int.user_dict.roles.insert(role.id, role.name);
+                }
+                self.flag_all();
+            }
+            Product::Uploaded(Ok(count)) => self.notice(format!("Uploaded {} file(s).", count)),
+            Product::Uploaded(Err(why)) => self.notice(format!("Upload failed: {}", why)),
+            Product::User(id, result) => {
+                // a user that couldn't be looked up is asked for again the next time they're drawn
+                self.int.user_dict.pending.remove(&id);
+                if let Ok(user) = result {
+                    self.int.user_dict.contents.entry(id).or_insert_with(|| UserInfo {
+                        name: user.name,
+                        color: Color::new(),
+                    });
+                    self.servers.get3().flag();
+                }
+            }
             Product::CanSave | Product::Killed =>return true,
             Product::Can(val) => self.handle_response(val),
         }
@@ -256,16 +484,125 @@ impl Parser {
     fn add_message(&mut self, message: Message) {
         let ch = message.channel_id;
         let guild = message.guild_id;
-        let res = self.servers.find_channel(ch, guild);
-        res.receive_message(&mut self.int.user_dict, &self.io.tasks, message);
+        // every DM is treated as a mention
+        let mentioned = guild.is_none()
+            || message.mention_everyone
+            || message.mentions.iter().any(|x| x.id == self.int.me);
+        let own = message.author.id == self.int.me;
+        let pos = match self.servers.find_pos(ch, guild) {
+            Ok(val) => val,
+            Err(_) => return,
+        };
+        let viewing = self.viewing(pos);
+        let res = self.servers.grab3(pos.0, pos.1, pos.2);
+        if res.receive_message(&mut self.int.user_dict, &self.io.tasks, message) && !viewing && !own && self.servers.notify(pos, mentioned) {
+            if let Messages::Loaded(val) = self.servers.grab3(pos.0, pos.1, pos.2) {
+                val.mark(val.labels.len() - 1);
+            }
+        }
+    }
+    /// Whether the channel at the given position is open and scrolled to the bottom.
+    fn viewing(&mut self, pos: (usize, usize, usize)) -> bool {
+        self.int.grid.messages_selected()
+            && self.servers.selected_pos() == pos
+            && matches!(self.servers.get3(), Messages::Loaded(val) if val.at_bottom())
+    }
+    /// Clears the unread state of the open channel once it's been scrolled to the bottom.
+    fn check_read(&mut self) {
+        let pos = self.servers.selected_pos();
+        if self.viewing(pos) {
+            self.servers.clear(pos);
+        }
+    }
+    fn edit_message(&mut self, event: MessageUpdateEvent) {
+        let ch = event.channel_id;
+        let guild = event.guild_id;
+        if let Some(res) = self.servers.find_channel(ch, guild) {
+            res.receive_edit(&self.io.tasks, event);
+        }
     }
     fn parse_none(&mut self, input: KeyEvent) {
-        match self.int.grid.context {
-            Context::Server => self.parse_none_server(input),
-            Context::Category => self.parse_none_category(input),
-            Context::Channel => self.parse_none_channel(input),
-            Context::Message => self.parse_none_message(input),
+        self.parse_key(keymap::Mode::Normal, input);
+    }
+    /// Looks a key up in the keymap and performs whatever it's bound to in the focused pane.
+    fn parse_key(&mut self, mode: keymap::Mode, input: KeyEvent) {
+        // the focus may have been moved onto a hidden pane since the last key
+        self.int.grid.show_focused();
+        if self.parse_count(input) {
+            return;
         }
+        match self.int.keymap.get(mode, &self.int.grid.context, input) {
+            Some(action) => self.perform(mode, action),
+            None => self.int.count = 0,
+        }
+    }
+    /// Performs an action as if its key was pressed in the given mode.
+    pub fn perform(&mut self, mode: keymap::Mode, action: Action) {
+        let count = std::mem::replace(&mut self.int.count, 0).max(1);
+        let global = matches!(
+            action,
+            Action::Filter
+                | Action::GlobalSearch
+                | Action::Help
+                | Action::WidenPane
+                | Action::NarrowPane
+                | Action::ToggleServers
+                | Action::ToggleCategories
+                | Action::ToggleFocus
+                | Action::ResetLayout
+        );
+        // everything else in the message pane works on the loaded channel
+        if !global && self.int.grid.context == Context::Message && !self.prepare_messages() {
+            return;
+        }
+        if let Some(motion) = Motion::from_action(action) {
+            return self.apply_motion(self.int.grid.context, motion, count);
+        }
+        match action {
+            Action::Filter => return self.start_filter(),
+            Action::GlobalSearch => return self.start_global_search(),
+            Action::Help => return self.start_help(mode),
+            Action::WidenPane | Action::NarrowPane => {
+                if !self.int.grid.resize(action == Action::WidenPane) {
+                    self.notice("This pane can't be resized any further.".to_string());
+                }
+                return self.layout_changed();
+            }
+            Action::ToggleServers => {
+                self.int.grid.toggle_servers();
+                return self.layout_changed();
+            }
+            Action::ToggleCategories => {
+                self.int.grid.toggle_categories();
+                return self.layout_changed();
+            }
+            Action::ToggleFocus => {
+                self.int.grid.toggle_focus();
+                return self.layout_changed();
+            }
+            Action::ResetLayout => {
+                self.int.grid.reset_layout();
+                return self.layout_changed();
+            }
+            _ => {}
+        }
+        match (mode, &self.int.grid.context) {
+            (keymap::Mode::Normal, Context::Server) => self.parse_none_server(action),
+            (keymap::Mode::Normal, Context::Category) => self.parse_none_category(action),
+            (keymap::Mode::Normal, Context::Channel) => self.parse_none_channel(action),
+            (keymap::Mode::Normal, Context::Message) => self.parse_none_message(action),
+            (keymap::Mode::Visual, Context::Server) => self.parse_visual_servers(action),
+            (keymap::Mode::Visual, Context::Category) => self.parse_visual_categories(action),
+            (keymap::Mode::Visual, Context::Channel) => self.parse_visual_channels(action),
+            (keymap::Mode::Visual, Context::Message) => self.parse_visual_messages(action),
+        }
+        // the focus can't be left on a hidden pane
+        self.int.grid.show_focused();
+    }
+    /// Redraws everything after the columns were resized, hidden or shown.
+    fn layout_changed(&mut self) {
+        let _ = queue!(self.io.out, Clear(ClearType::All));
+        self.reset_all();
     }
     fn parse_message(&mut self, input: KeyEvent) {
         let KeyEvent { code, modifiers: _ } = input;
@@ -273,25 +610,47 @@ impl Parser {
             KeyCode::Backspace => {
                 self.message_box.backspace();
             }
+            KeyCode::Enter if self.int.edit.is_some() => {
+                self.finish_edit_text(true);
+            }
+            KeyCode::Esc if self.int.edit.is_some() => {
+                self.finish_edit_text(false);
+            }
+            KeyCode::Enter if self.message_box.text().starts_with("/attach") => {
+                let text = self.message_box.flush();
+                self.attach(text.trim_start_matches("/attach").trim());
+                self.reset_all();
+            }
             KeyCode::Enter => {
-                let message = &json!({"content": self.message_box.flush()});
+                let mut message = json!({"content": self.message_box.flush()});
+                if let Some(reply) = self.int.reply.take() {
+                    // the reply is dropped if the user has since moved to another channel
+                    if self.servers.get3().id().map(|x| x.id()) == Some(reply.channel) {
+                        message["message_reference"] = json!({"message_id": reply.message.0});
+                    }
+                }
+                if !self.int.attachments.is_empty() {
+                    let files = std::mem::take(&mut self.int.attachments);
+                    self.int.grid.update_msg(1);
+                    self.reset_all();
+                    if let Some(ch) = self.servers.get3().id() {
+                        // the files go in a single request, so the total size is all there is to report
+                        let bytes: u64 = files.iter().filter_map(|x| std::fs::metadata(x).ok()).map(|x| x.len()).sum();
+                        self.notice(format!("Uploading {} file(s), {:.1} MB...", files.len(), bytes as f64 / 1_000_000.0));
+                        self.io.tasks.send(Task::Upload(ch.id(), message, files)).expect("Could not send!");
+                    }
+                    return;
+                }
+                let message = &message;
                 self.int.grid.update_msg(1);
                 if let Some(val) = self.servers.get3().id().and_then(|x| x.guild()) {
                     if let Err(why) = block_on(self.http().send_message(val.id.0, message)) {
-                        let v = why.to_string();
-                        self.temp_box.flush();
-                        self.temp_box.add_to_end(vec![v]);
-                        let temp = (self.int.grid.end_y - self.temp_box.lines()) as u16;
-                        self.temp_box.draw(0, temp, &mut self.io.out, false);
+                        self.notice(why.to_string());
                     }
                 }
                 if let Some(val) = self.servers.get3().id().and_then(|x| x.private()) {
                     if let Err(why) = block_on(self.http().send_message(val.id.0, message)) {
-                        let v = why.to_string();
-                        self.temp_box.flush();
-                        self.temp_box.add_to_end(vec![v]);
-                        let temp = (self.int.grid.end_y - self.temp_box.lines()) as u16;
-                        self.temp_box.draw(0, temp, &mut self.io.out, false);
+                        self.notice(why.to_string());
                     }
                 }
                 self.reset_all();
@@ -308,6 +667,10 @@ impl Parser {
             }
             KeyCode::Esc => {
                 self.int.state = State::None;
+                if self.int.reply.take().is_some() || !self.int.attachments.is_empty() {
+                    self.int.attachments.clear();
+                    self.reset_all();
+                }
             }
             KeyCode::Tab => {
                 self.message_box.newline();
@@ -315,27 +678,163 @@ impl Parser {
             _ => todo!(),
         }
     }
+    /// The lines shown above the message box: the reply preview and any pending attachments.
+    fn header(&self) -> Vec<String> {
+        let mut result = Vec::new();
+        if self.int.state == State::React {
+            let recent: Vec<String> = self.int.recent_emoji.iter().enumerate().map(|(i, x)| format!("{}:{}", i + 1, emoji::display(x))).collect();
+            result.push(format!("React with an emoji or :name: (recent {})", recent.join(" ")));
+            return result;
+        }
+        if self.int.state == State::Date {
+            result.push("Jump to the first message on a date (YYYY-MM-DD)".to_string());
+            return result;
+        }
+        if self.int.state == State::Bookmark {
+            result.push("Name the bookmark".to_string());
+            return result;
+        }
+        let connected = &self.servers.selected_label().connected;
+        if !connected.is_empty() {
+            let names: Vec<&str> = connected.iter().map(|x| x.1.as_str()).collect();
+            result.push(format!("In voice: {}", names.join(", ")));
+        }
+        if let Some(reply) = &self.int.reply {
+            result.push(reply.preview.clone());
+        }
+        if !self.int.attachments.is_empty() {
+            let names: Vec<&str> = self.int.attachments.iter().map(|x| file_name(x)).collect();
+            result.push(format!("Attached: {}", names.join(", ")));
+        }
+        result
+    }
+    /// Starts a reply to the current message. If `quote` is set, the message is quoted in the message box instead.
+    pub fn reply(&mut self, quote: bool) {
+        let (channel, msg) = match self.servers.get3() {
+            Messages::Loaded(val) => match val.labels.get(val.current) {
+                Some(msg) => (val.id.id(), msg.clone()),
+                None => return,
+            },
+            _ => return,
+        };
+        if quote {
+            let mut lines: Vec<String> = msg.content.content.iter().map(|x| format!("> {}", x)).collect();
+            lines.push(String::new());
+            if !self.message_box.text().is_empty() {
+                lines.insert(0, String::new());
+            }
+            self.message_box.add_to_end(lines);
+            self.message_box.end();
+        } else {
+            let snippet = msg.content.content.first().cloned().unwrap_or_default();
+            self.int.reply = Some(Reply {
+                channel,
+                message: msg.id,
+                preview: format!("Replying to {}: {}", msg.username, snippet),
+            });
+        }
+        self.start_message();
+        self.reset_all();
+    }
+    /// Starts typing a message, unless the open channel can't be written in.
+    pub fn start_message(&mut self) {
+        match self.read_only() {
+            Some(why) => self.notice(why.to_string()),
+            None => self.int.state = State::Message,
+        }
+    }
+    /// Why messages can't be sent in the open channel, if they can't.
+    fn read_only(&mut self) -> Option<&'static str> {
+        let channel = match self.servers.get3().id() {
+            Some(Channel::Guild(val)) => val,
+            _ => return None,
+        };
+        if channel.kind == ChannelType::Store {
+            return Some("Store channels can't be written in.");
+        }
+        // without knowing the user's roles, the server gets the final say
+        let membership = self.int.memberships.get(&channel.guild_id)?;
+        if membership.permissions(&channel, self.int.me).contains(Permissions::SEND_MESSAGES) {
+            None
+        } else {
+            Some("You don't have permission to send messages in this channel.")
+        }
+    }
+    /// Draws the header above the message box if `flag` is set. Returns its height.
+    fn draw_header(&mut self, flag: bool) -> u16 {
+        let header = self.header();
+        if flag {
+            for (i, line) in header.iter().enumerate() {
+                let line: String = line
+                    .graphemes(true)
+                    .chain(" ".graphemes(true).cycle())
+                    .take(self.int.grid.total_across())
+                    .collect();
+                let (x, y) = (self.int.grid.start_x as u16, (self.int.grid.border_y + i) as u16);
+                let _ = queue!(self.io.out, MoveTo(x, y), Print(ansi::LOW_INTENSITY.to_string() + &line + ansi::RESET));
+            }
+        }
+        header.len() as u16
+    }
+    /// Adds a file to upload with the next message.
+    fn attach(&mut self, path: &str) {
+        if path.is_empty() {
+            self.notice("Usage: /attach <path>".to_string());
+        } else if std::path::Path::new(path).is_file() {
+            self.int.attachments.push(path.to_string());
+        } else {
+            self.notice(format!("Could not find the file {}!", path));
+        }
+    }
+    /// Shows a one-off message (usually an error) at the bottom of the screen.
+    fn notice(&mut self, v: String) {
+        self.temp_box.flush();
+        self.temp_box.add_to_end(vec![v]);
+        let temp = (self.int.grid.end_y - self.temp_box.lines()) as u16;
+        self.temp_box.draw(0, temp, &mut self.io.out, false);
+    }
     fn draw(&mut self) {
         if self.message_box.flag {
             let prev = self.int.grid.border_y;
             self.int.grid.border_y =
-                self.int.grid.end_y - self.message_box.lines().min(self.int.grid.max_box_len).max(1);
+                self.int.grid.end_y - self.message_box.lines().min(self.int.grid.max_box_len).max(1) - self.header().len();
             if self.int.grid.border_y != prev {
                 self.flag_all();
             }
         }
-        self.servers.draw(&self.int.grid, &mut self.io.out);
-        self.servers.get().draw(&self.int.grid, &mut self.io.out);
-        self.servers.get2().draw(&self.int.grid, &mut self.io.out);
-        self.servers
+        // hidden lists keep their flags until they're shown again
+        if self.int.grid.visible(Context::Server) {
+            self.servers.draw(&self.int.grid, &mut self.io.out);
+        }
+        if self.int.grid.visible(Context::Category) {
+            self.servers.get().draw(&self.int.grid, &mut self.io.out);
+        }
+        if self.int.grid.visible(Context::Channel) {
+            self.servers.get2().draw(&self.int.grid, &mut self.io.out);
+        }
+        let redrawn = self.servers
             .get3()
             .draw(&self.int.grid, &mut self.io.out, &mut self.int.user_dict, &self.io.tasks);
-        self.message_box.draw(
-            self.int.grid.start_x as u16,
-            self.int.grid.border_y as u16,
-            &mut self.io.out,
-            true,
-        );
+        let start_x = self.int.grid.start_x as u16;
+        let border_y = self.int.grid.border_y as u16;
+        match self.int.state {
+            State::Filter => {
+                if redrawn {
+                    self.filter.flag();
+                }
+                // the results are drawn over the message pane
+                self.filter.draw(&self.int.grid, &mut self.io.out);
+                self.filter.query.draw(start_x, border_y, &mut self.io.out, true);
+            }
+            State::Search | State::React | State::Date | State::Bookmark => {
+                let box_y = border_y + self.draw_header(self.prompt_box.flag);
+                self.prompt_box.draw(start_x, box_y, &mut self.io.out, true);
+            }
+            _ => {
+                let box_y = border_y + self.draw_header(self.message_box.flag);
+                self.message_box.draw(start_x, box_y, &mut self.io.out, true);
+            }
+        }
         let _ = execute!(self.io.out);
     }
     fn parse_quit_start(&mut self) {
@@ -394,56 +893,21 @@ impl Parser {
     fn http(&self) -> Arc<Http> {
         Arc::clone(&self.io.client.cache_and_http.http)
     }
-    fn network_update_first(&mut self) {
-        let v = block_on(
-            self.http()
-                .get_guilds(&GuildPagination::After(GuildId(0)), 100),
-        );
-        if let Ok(val) = v {
-            let result = self.network_update_channels(val.clone());
-            for (item, server) in result.into_iter().zip(val) {
-                // each server
-                self.servers.add(server.name, None, server.id);
-                for (category, item) in item {
-                    // each category
-                    if let Some(_) = category {
-                        self.servers.last().add(
-                            item.category
-                                .clone()
-                                .and_then(|x| Some(x.name))
-                                .unwrap_or("unnamed category".to_string()),
-                            None,
-                            item.category.clone(),
-                        );
-                        for channel in item.channels {
-                            // each channel
-                            self.servers.last2().add(
-                                channel.name.clone(),
-                                None,
-                                Channel::Guild(channel),
-                            );
-                        }
-                    } else {
-                        for channel in item.channels {
-                            // each channel
-                            self.servers.last().grab(0).add(
-                                channel.name.clone(),
-                                None,
-                                Channel::Guild(channel),
-                            );
-                        }
-                    }
-                }
+    /// Lists every server the user is in, a page of 100 at a time.
+    fn get_all_guilds(&self) -> serenity::Result<Vec<GuildInfo>> {
+        let mut result: Vec<GuildInfo> = Vec::new();
+        loop {
+            let last = result.last().map(|x| x.id).unwrap_or(GuildId(0));
+            let page = block_on(self.http().get_guilds(&GuildPagination::After(last), GUILD_PAGE))?;
+            let done = page.len() < GUILD_PAGE as usize;
+            result.extend(page);
+            if done {
+                return Ok(result);
             }
         }
-        self.load_dms();
     }
-    
-    fn network_update_subsequent(&mut self) {
-        let v = block_on(
-            self.http()
-                .get_guilds(&GuildPagination::After(GuildId(0)), 100),
-        );
+    fn network_update_first(&mut self) {
+        let v = self.get_all_guilds();
         if let Ok(val) = v {
             let result = self.network_update_channels(val.clone());
             for (item, server) in result.into_iter().zip(val) {
@@ -482,6 +946,8 @@ impl Parser {
             }
         }
         self.load_dms();
+        self.record_names();
+        self.load_guild_info();
     }
     fn network_update_channels(
         &mut self,
@@ -494,17 +960,17 @@ impl Parser {
             let mut temp: HashMap<Option<ChannelId>, Category> = HashMap::new();
             for line in v {
                 match line.kind {
-                    ChannelType::Text => {
-                        temp.entry(line.category_id)
-                            .or_insert_with(Category::new)
-                            .channels
-                            .push(line);
-                    }
                     ChannelType::Category => {
                         temp.entry(Some(line.id))
                             .or_insert_with(Category::new)
                             .category = Some(line.clone());
                     }
+                    kind if crate::channels::is_listed(kind) => {
+                        temp.entry(line.category_id)
+                            .or_insert_with(Category::new)
+                            .channels
+                            .push(line);
+                    }
                     _ => {}
                 }
             }
@@ -530,9 +996,46 @@ impl Parser {
         let result = v.len() == 50;
         (v, result)
     }
+    /// Records the name of every listed channel, so that mentions of them can be shown.
+    fn record_names(&mut self) {
+        for categories in &self.servers.contents {
+            for channels in &categories.contents {
+                for (label, messages) in channels.labels.iter().zip(channels.contents.iter()) {
+                    if let Some(channel) = messages.id() {
+                        self.int.user_dict.channels.insert(channel.id(), label.name().to_string());
+                    }
+                }
+            }
+        }
+    }
+    /// Looks up the active threads, the roles and the user's own roles of every server in the background.
+    fn load_guild_info(&mut self) {
+        for guild in self.servers.contents.iter().filter_map(|x| x.s_id) {
+            self.io.tasks.send(Task::GetGuildInfo(guild, self.int.me)).expect("Could not send!");
+        }
+    }
+    /// Brings a saved tree up to date, adding servers that are new since it was saved
+    /// and marking the ones that were left as gone. Without a connection the save is kept as is.
+    /// Channels are brought up to date as each server arrives from the gateway.
+    fn network_update_saved(&mut self) {
+        // servers missing from an incomplete list would be marked as gone
+        let guilds = match self.get_all_guilds() {
+            Ok(val) => val,
+            Err(_) => return,
+        };
+        self.servers.reconcile(guilds.into_iter().map(|x| (x.id, x.name)).collect());
+        self.load_dms();
+        self.record_names();
+        self.load_guild_info();
+    }
+    /// Lists every DM channel that isn't listed yet.
     pub fn load_dms(&mut self) {
         let dms = block_on(self.http().get_user_dm_channels()).expect("WHY?!!");
         for dm in dms {
+            let listed = self.servers.grab2(0, 0).contents.iter().any(|x| x.id().map(|x| x.id() == dm.id).unwrap_or(false));
+            if listed {
+                continue;
+            }
             self.servers.grab2(0, 0).add(
                 dm.name().split(' ').skip(2).next().unwrap().to_string(),
                 None,
@@ -563,13 +1066,26 @@ impl Parser {
         }
     }
     pub fn parse_visual(&mut self, event: KeyEvent) {
-        match self.int.grid.context {
-            Context::Server => self.parse_visual_servers(event),
-            Context::Category => self.parse_visual_categories(event),
-            Context::Channel => self.parse_visual_channels(event),
-            Context::Message => self.parse_visual_messages(event),
+        self.parse_key(keymap::Mode::Visual, event);
+    }
+}
+fn file_name(path: &str) -> &str {
+    std::path::Path::new(path)
+        .file_name()
+        .and_then(|x| x.to_str())
+        .unwrap_or(path)
+}
+/// Looks up the logged in user, trying a few times before giving up.
+fn current_user(client: &Client) -> Result<UserId, String> {
+    let mut why = String::new();
+    for _ in 0..3 {
+        match block_on(client.cache_and_http.http.get_current_user()) {
+            Ok(val) => return Ok(val.id),
+            Err(val) => why = val.to_string(),
         }
+        std::thread::sleep(Duration::from_secs(1));
     }
+    Err(why)
 }
 #[derive(Clone)]
 struct Category {
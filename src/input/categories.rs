@@ -1,65 +1,48 @@
-use crossterm::event::{KeyCode, KeyEvent};
+use crate::keymap::Action;
 
 use super::State;
 
 impl super::Parser {
-    pub fn parse_none_category(&mut self, input: KeyEvent) {
-        let KeyEvent { code, modifiers: _ } = input;
-        match code {
-            KeyCode::Left => {
+    pub fn parse_none_category(&mut self, action: Action) {
+        match action {
+            Action::FocusLeft => {
                 self.int.grid.context = super::Context::Server;
                 self.servers.get().flag();
                 self.servers.flag();
             }
-            KeyCode::Right => {
+            Action::FocusRight => {
                 self.int.grid.context = super::Context::Channel;
                 self.servers.get().flag();
                 self.servers.get2().flag();
             }
-            KeyCode::Up => {
-                self.servers.get().up();
-            }
-            KeyCode::Down => {
-                self.servers.get().down();
-            }
-            KeyCode::Enter => {
+            Action::Select => {
                 self.servers.get().select();
                 self.reset_all();
             }
-            KeyCode::Char('t') => {
-                self.int.state = State::Message;
-            }
-            KeyCode::Char('f') => {
-                self.int.state = State::Filter;
+            Action::Type => {
+                self.start_message();
             }
             _ => {}
         }
     }
-    pub fn parse_visual_categories(&mut self, input: KeyEvent) {
-        let KeyEvent { code, modifiers: _ } = input;
-        match code {
-            KeyCode::Backspace | KeyCode::Delete | KeyCode::Esc => self.int.state = State::None,
-            KeyCode::Left => {
+    pub fn parse_visual_categories(&mut self, action: Action) {
+        match action {
+            Action::ExitVisual => self.int.state = State::None,
+            Action::FocusLeft => {
                 self.int.grid.context = super::Context::Server;
                 self.servers.get().flag();
                 self.servers.flag();
             }
-            KeyCode::Right => {
+            Action::FocusRight => {
                 self.int.grid.context = super::Context::Channel;
                 self.servers.get().flag();
                 self.servers.get2().flag();
             }
-            KeyCode::Up => {
-                self.servers.get().up();
-            }
-            KeyCode::Down => {
-                self.servers.get().down();
-            }
-            KeyCode::Enter => {
+            Action::Select => {
                 self.servers.get().select();
                 self.reset_all();
             }
-            KeyCode::Char('c') => {
+            Action::Color => {
                 self.servers.get().color();
             },
             _ => {}
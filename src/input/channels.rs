@@ -1,59 +1,45 @@
-use crossterm::event::{KeyCode, KeyEvent};
+use crate::keymap::Action;
 
 use super::State;
 
 impl super::Parser {
-    pub fn parse_none_channel(&mut self, input: KeyEvent) {
-        let KeyEvent { code, modifiers: _ } = input;
-        match code {
-            KeyCode::Left => {
+    pub fn parse_none_channel(&mut self, action: Action) {
+        match action {
+            Action::FocusLeft => {
                 self.int.grid.context = super::Context::Category;
                 self.reset_all();
             }
-            KeyCode::Right => {
+            Action::FocusRight => {
                 self.int.grid.context = super::Context::Message;
                 self.reset_all();
             }
-            KeyCode::Up => {
-                self.servers.get2().up();
-            }
-            KeyCode::Down => {
-                self.servers.get2().down();
-            }
-            KeyCode::Enter => {
+            Action::Select => {
                 self.servers.get2().select();
                 self.reset_all();
             }
-            KeyCode::Char('t') => {
-                self.int.state = State::Message;
+            Action::Type => {
+                self.start_message();
             }
             _ => {}
         }
     }
-    pub fn parse_visual_channels(&mut self, input: KeyEvent) {
-        let KeyEvent { code, ..}: KeyEvent = input;
-        match code {
-            KeyCode::Left => {
+    pub fn parse_visual_channels(&mut self, action: Action) {
+        match action {
+            Action::FocusLeft => {
                 self.int.grid.context = super::Context::Category;
                 self.reset_all();
             }
-            KeyCode::Right => {
+            Action::FocusRight => {
                 self.int.grid.context = super::Context::Message;
                 self.reset_all();
             }
-            KeyCode::Up => {
-                self.servers.get2().up();
-            }
-            KeyCode::Down => {
-                self.servers.get2().down();
-            }
-            KeyCode::Enter => {
+            Action::Select => {
                 self.servers.get2().select();
                 self.reset_all();
             }
-            KeyCode::Char('c') => self.servers.get2().color(),
-            KeyCode::Char('s') => self.servers.get2().select_color(),
-            KeyCode::Esc | KeyCode::Delete | KeyCode::Backspace => self.int.state = State::None,
+            Action::Color => self.servers.get2().color(),
+            Action::SelectColor => self.servers.get2().select_color(),
+            Action::ExitVisual => self.int.state = State::None,
             _ => {},
         }
     }
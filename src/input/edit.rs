@@ -0,0 +1,140 @@
+use chrono::Local;
+use crossterm::event::{KeyCode, KeyEvent};
+use serde_json::json;
+use serenity::model::id::{ChannelId, GuildId, MessageId};
+
+use crate::{block_on::block_on, messages::Messages};
+
+use super::State;
+
+pub enum Change {
+    None,
+    Text(String),
+    Delete,
+}
+/// A pending change to one of the user's own messages.
+pub struct Edit {
+    pub channel: ChannelId,
+    pub guild: Option<GuildId>,
+    pub message: MessageId,
+    pub change: Change,
+    pub draft: Option<String>, // whatever was in the message box before editing started
+}
+const HELP: &str = "Edit mode: t to edit text, d to delete, c to commit, n to cancel, esc to exit";
+
+impl super::Parser {
+    pub fn start_edit(&mut self) {
+        let me = self.int.me;
+        let channel = match self.servers.get3().id() {
+            Some(val) => val,
+            None => return,
+        };
+        let guild = channel.clone().guild().map(|x| x.guild_id);
+        let msg = match self.servers.get3() {
+            Messages::Loaded(val) => val.labels.get(val.current),
+            _ => None,
+        };
+        match msg {
+            Some(msg) if msg.user == me && !msg.deleted => {
+                self.int.edit = Some(Edit {
+                    channel: channel.id(),
+                    guild,
+                    message: msg.id,
+                    change: Change::None,
+                    draft: None,
+                });
+                self.int.state = State::Edit;
+                self.notice(HELP.to_string());
+            }
+            Some(_) => self.notice("You can only edit or delete your own messages.".to_string()),
+            None => {}
+        }
+    }
+    pub fn parse_edit(&mut self, input: KeyEvent) {
+        let KeyEvent { code, modifiers: _ } = input;
+        match code {
+            KeyCode::Char('t') => {
+                // the latest revision, whichever one is being shown
+                let content = match self.servers.get3() {
+                    Messages::Loaded(val) => val.labels.get_mut(val.current).map(|x| x.last().content.clone()),
+                    _ => None,
+                };
+                if let (Some(content), Some(edit)) = (content, &mut self.int.edit) {
+                    edit.draft = Some(self.message_box.flush());
+                    self.message_box.add_to_end(content);
+                    self.int.state = State::Message;
+                }
+            }
+            KeyCode::Char('d') => {
+                if let Some(edit) = &mut self.int.edit {
+                    edit.change = Change::Delete;
+                }
+                self.notice("This message will be deleted. Press c to commit or n to cancel.".to_string());
+            }
+            KeyCode::Char('n') => {
+                if let Some(edit) = &mut self.int.edit {
+                    edit.change = Change::None;
+                }
+                self.notice(HELP.to_string());
+            }
+            KeyCode::Char('c') => {
+                self.commit_edit();
+            }
+            KeyCode::Esc => {
+                self.int.edit = None;
+                self.int.state = State::None;
+                self.reset_all();
+            }
+            _ => {}
+        }
+    }
+    /// Leaves the message box, keeping the new text if `keep` is set, and restores the draft.
+    pub fn finish_edit_text(&mut self, keep: bool) {
+        let text = self.message_box.flush();
+        if let Some(edit) = &mut self.int.edit {
+            if keep {
+                edit.change = Change::Text(text);
+            }
+            if let Some(draft) = edit.draft.take() {
+                self.message_box.add_to_end(draft.split('\n').map(|x| x.to_string()).collect());
+            }
+        }
+        self.int.state = State::Edit;
+        self.reset_all();
+        if keep {
+            self.notice("Press c to commit the edit or n to cancel.".to_string());
+        }
+    }
+    fn commit_edit(&mut self) {
+        let edit = match self.int.edit.take() {
+            Some(val) => val,
+            None => return,
+        };
+        self.int.state = State::None;
+        self.reset_all();
+        let Edit { channel, guild, message, change, .. } = edit;
+        match change {
+            Change::None => {}
+            Change::Text(text) => {
+                let map = json!({ "content": text });
+                match block_on(self.http().edit_message(channel.0, message.0, &map)) {
+                    Ok(val) => {
+                        let time = val.edited_timestamp.map(|x| x.with_timezone(&Local)).unwrap_or_else(Local::now);
+                        if let Some(Messages::Loaded(loaded)) = self.servers.find_channel(channel, guild) {
+                            loaded.revise(message, &val.content, time);
+                        }
+                    }
+                    Err(why) => self.notice(why.to_string()),
+                }
+            }
+            Change::Delete => match block_on(self.http().delete_message(channel.0, message.0)) {
+                Ok(()) => {
+                    if let Some(Messages::Loaded(loaded)) = self.servers.find_channel(channel, guild) {
+                        loaded.receive_delete(vec![message]);
+                    }
+                }
+                Err(why) => self.notice(why.to_string()),
+            },
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use crossterm::event::{KeyCode, KeyEvent};
+
+use serenity::model::id::ChannelId;
+
+use crate::{api, block_on::block_on, filter::{Mode, Target}, keymap, messages::Messages};
+
+use super::{Context, State};
+
+impl super::Parser {
+    pub fn start_filter(&mut self) {
+        self.int.state = State::Filter;
+        self.filter.mode = Mode::Channels;
+        self.filter.query.flush();
+        self.refresh_filter();
+        self.filter.flag();
+    }
+    pub fn start_bookmarks(&mut self) {
+        self.int.state = State::Filter;
+        self.filter.mode = Mode::Bookmarks;
+        self.filter.query.flush();
+        self.refresh_filter();
+        self.filter.flag();
+    }
+    /// Lists the bindings for the focused pane, any of which can be performed by selecting it.
+    pub fn start_help(&mut self, mode: keymap::Mode) {
+        self.int.state = State::Filter;
+        self.filter.mode = Mode::Help(mode);
+        self.filter.query.flush();
+        self.refresh_filter();
+        self.filter.flag();
+    }
+    pub fn start_global_search(&mut self) {
+        self.int.state = State::Filter;
+        self.filter.mode = Mode::Messages;
+        self.filter.query.flush();
+        self.int.index.update(&mut self.servers);
+        self.refresh_filter();
+        self.filter.flag();
+    }
+    fn refresh_filter(&mut self) {
+        match self.filter.mode {
+            Mode::Channels => self.filter.update(&self.servers),
+            Mode::Messages => self.filter.update_messages(&self.int.index),
+            Mode::Bookmarks => self.filter.update_bookmarks(&self.servers),
+            Mode::Help(mode) => self.filter.update_help(&self.int.keymap, mode, &self.int.grid.context),
+        }
+    }
+    pub fn parse_filter(&mut self, input: KeyEvent) {
+        let KeyEvent { code, modifiers: _ } = input;
+        match code {
+            KeyCode::Esc => {
+                self.int.state = State::None;
+                self.reset_all();
+            }
+            KeyCode::Enter => match self.filter.get() {
+                Some(Target::Action(mode, action)) => {
+                    self.int.state = match mode {
+                        keymap::Mode::Normal => State::None,
+                        keymap::Mode::Visual => State::Visual,
+                    };
+                    self.reset_all();
+                    self.perform(mode, action);
+                }
+                target => {
+                    if let Some(target) = target {
+                        self.jump(target);
+                    }
+                    self.int.state = State::None;
+                    self.reset_all();
+                }
+            },
+            KeyCode::Up => self.filter.up(),
+            KeyCode::Down => self.filter.down(),
+            KeyCode::Left => self.filter.query.left(),
+            KeyCode::Right => self.filter.query.right(),
+            KeyCode::Backspace => {
+                self.filter.query.backspace();
+                self.refresh_filter();
+            }
+            KeyCode::Delete => {
+                self.filter.query.delete();
+                self.refresh_filter();
+            }
+            KeyCode::Char(val) => {
+                self.filter.query.add_char(val);
+                self.refresh_filter();
+            }
+            _ => {}
+        }
+    }
+    /// Switches to the first channel mentioned in the current message.
+    pub fn jump_to_mention(&mut self) {
+        let (channel, guild) = match self.servers.get3() {
+            Messages::Loaded(val) => match val.mentioned_channel() {
+                Some(channel) => (channel, val.id.clone().guild().map(|x| x.guild_id)),
+                None => return,
+            },
+            _ => return,
+        };
+        match self.servers.find_pos(channel, guild) {
+            Ok((server, category, channel)) => {
+                self.jump(Target::Channel(server, category, channel));
+                self.reset_all();
+            }
+            Err(_) => self.notice("That channel isn't in the server list.".to_string()),
+        }
+    }
+    /// Switches to the thread started from the current message, looking it up if it isn't listed.
+    pub fn open_thread(&mut self) {
+        let (thread, guild) = match self.servers.get3() {
+            Messages::Loaded(val) => match (val.labels.get(val.current), val.id.clone().guild()) {
+                // threads started from a message share its id
+                (Some(msg), Some(channel)) => (ChannelId(msg.id.0), channel.guild_id),
+                _ => return,
+            },
+            _ => return,
+        };
+        if self.servers.find_pos(thread, Some(guild)).is_err() {
+            match block_on(api::get_thread(&self.http(), thread)) {
+                Ok(Some(val)) => {
+                    self.int.user_dict.channels.insert(val.id, val.name.clone());
+                    self.servers.add_thread(val);
+                }
+                Ok(None) => return self.notice("This message doesn't have a thread.".to_string()),
+                Err(why) => return self.notice(format!("Couldn't look up the thread: {}", why)),
+            }
+        }
+        if let Ok((server, category, channel)) = self.servers.find_pos(thread, Some(guild)) {
+            self.jump(Target::Channel(server, category, channel));
+            self.reset_all();
+        }
+    }
+    /// Switches to a server, category, channel or message, leaving the cursor on it.
+    fn jump(&mut self, target: Target) {
+        let (server, category, channel) = match target {
+            Target::Server(server) => {
+                let category = self.servers.grab(server).selected;
+                (server, category, self.servers.grab2(server, category).selected)
+            }
+            Target::Category(server, category) => {
+                (server, category, self.servers.grab2(server, category).selected)
+            }
+            Target::Channel(server, category, channel) => (server, category, channel),
+            Target::Message(channel, guild, _) => match self.servers.find_pos(channel, guild) {
+                Ok(val) => val,
+                Err(_) => return,
+            },
+            Target::Action(..) => return,
+        };
+        self.servers.switch3(server, category, channel);
+        self.servers.current = server;
+        self.servers.get().current = category;
+        self.servers.get2().current = channel;
+        self.int.grid.context = match target {
+            Target::Server(_) => Context::Server,
+            Target::Category(..) => Context::Category,
+            Target::Message(_, _, id) => match self.servers.get3() {
+                Messages::Loaded(val) => {
+                    if val.jump_to(id) {
+                        Context::Message
+                    } else {
+                        Context::Channel
+                    }
+                }
+                _ => Context::Channel,
+            },
+            _ => Context::Channel,
+        };
+        // the target's pane may be hidden
+        self.int.grid.show_focused();
+    }
+}
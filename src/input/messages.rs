@@ -1,101 +1,296 @@
+use chrono::{Local, NaiveDate, TimeZone};
 use crossterm::event::{KeyCode, KeyEvent};
 
-use crate::messages::Messages;
+use serenity::model::channel::{Channel, ChannelType};
 
-use super::{Context, State};
+use crate::{block_on::block_on, file::{fs_write, fs_write_2}, highlight, keymap::Action, messages::{Messages, SearchResult}, task::Until};
+
+use super::{Context, REQUEST_LEN, State};
+
+const HISTORY_PAGES: usize = 10; // how many pages L loads at once
 
 impl super::Parser {
-    pub fn parse_none_message(&mut self, input: KeyEvent) {
-        let KeyEvent { code, modifiers: _ } = input;
+    /// Loads the current channel before a key is handled in the message pane.
+    /// Returns false if it can't be, moving the focus back to the channel pane.
+    pub fn prepare_messages(&mut self) -> bool {
         self.servers
             .get3()
             .update(&self.io.tasks);
         self.servers
             .get3()
             .draw(&self.int.grid, &mut self.io.out, &mut self.int.user_dict, &self.io.tasks);
-            match self.servers.get3() {
-                Messages::Unloaded(_) => {
-                    panic!("this should never happen!");
-                }
-                Messages::Loaded(_) => {}
-                _ => {
-                    self.int.grid.context = Context::Channel;
-                    self.reset_all();
-                    return;
-                }
+        match self.servers.get3() {
+            Messages::Unloaded(_) => {
+                panic!("this should never happen!");
             }
-        match code {
-            KeyCode::Left => {
+            Messages::Loaded(_) => true,
+            _ => {
+                self.int.grid.context = Context::Channel;
+                self.reset_all();
+                false
+            }
+        }
+    }
+    pub fn parse_none_message(&mut self, action: Action) {
+        match action {
+            Action::FocusLeft => {
                 self.int.grid.context = Context::Channel;
                 self.servers.get3().assume_loaded().flag();
                 self.servers.get2().flag();
             }
-            KeyCode::Up => {
-                self.servers.get3().assume_loaded().up(&self.int.grid);
-            }
-            KeyCode::Down => {
-                self.servers.get3().assume_loaded().down(&self.int.grid);
-            }
-            KeyCode::Enter => {
+            Action::Select => {
                 self.servers.get3().assume_loaded().select();
                 self.reset_all();
             }
-            KeyCode::Char('t') => {
-                self.int.state = State::Message;
+            Action::Type => {
+                self.start_message();
             }
-            KeyCode::Char('b') => {
+            Action::Back => {
                 self.servers.get3().assume_loaded().back();
             }
-            KeyCode::Char('v') => {
+            Action::EnterVisual => {
                 self.int.state = State::Visual;
             }
-            KeyCode::Char('m') => {
+            Action::MessagePerson => {
                 self.message_person();
             }
-            KeyCode::Char('o') => {
-                self.servers
+            Action::CycleRevision => {
+                self.servers.get3().assume_loaded().cycle_revision();
+            }
+            Action::ToggleDeleted => {
+                self.servers.get3().assume_loaded().toggle_deleted();
+            }
+            Action::ToggleSpoilers => {
+                self.servers.get3().assume_loaded().toggle_spoilers();
+            }
+            Action::JumpToMention => {
+                self.jump_to_mention();
+            }
+            Action::OpenThread => {
+                self.open_thread();
+            }
+            Action::SaveCode => {
+                self.save_code_block(false);
+            }
+            Action::OpenCode => {
+                self.save_code_block(true);
+            }
+            Action::Crosspost => {
+                self.crosspost();
+            }
+            Action::Edit => {
+                self.start_edit();
+            }
+            Action::Reply => {
+                self.reply(false);
+            }
+            Action::QuoteReply => {
+                self.reply(true);
+            }
+            Action::React => {
+                self.prompt_box.flush();
+                self.int.state = State::React;
+                self.reset_all();
+            }
+            Action::Search => {
+                self.prompt_box.flush();
+                self.int.state = State::Search;
+            }
+            Action::Bookmark => {
+                let loaded = self.servers.get3().assume_loaded();
+                match loaded.bookmark().map(|x| x.to_string()) {
+                    Some(name) => {
+                        loaded.set_bookmark(None);
+                        self.notice(format!("Removed the bookmark {}.", name));
+                    }
+                    None => {
+                        self.prompt_box.flush();
+                        self.int.state = State::Bookmark;
+                        self.reset_all();
+                    }
+                }
+            }
+            Action::Bookmarks => {
+                self.start_bookmarks();
+            }
+            Action::JumpToDate => {
+                self.prompt_box.flush();
+                self.int.state = State::Date;
+                self.reset_all();
+            }
+            Action::LoadHistory => {
+                if self.servers.get3().assume_loaded().load_history(&self.io.tasks, Until::Pages(HISTORY_PAGES)) {
+                    self.notice(format!("Loading up to {} older messages.", HISTORY_PAGES * REQUEST_LEN));
+                } else {
+                    self.notice("There are no older messages to load.".to_string());
+                }
+            }
+            Action::SearchOlder => {
+                let res = self.servers.get3().assume_loaded().search_older(&self.io.tasks, true);
+                self.search_notice(res);
+            }
+            Action::SearchNewer => {
+                let res = self.servers.get3().assume_loaded().search_newer();
+                self.search_notice(res);
+            }
+            Action::OpenAttachment => {
+                let opened = self.servers
                     .get3()
                     .assume_loaded()
-                    .open(&self.int.file_options, &self.int.grid);
+                    .open(&self.int.file_options, &self.int.grid, &self.int.user_dict);
+                if !opened {
+                    self.notice("This message has no files.".to_string());
+                }
             }
             _ => {}
         }
     }
-    pub fn parse_visual_messages(&mut self, input: KeyEvent) {
-        let KeyEvent {code, modifiers: _} = input;
-        self.servers
-            .get3()
-            .update(&self.io.tasks);
-        self.servers
-            .get3()
-            .draw(&self.int.grid, &mut self.io.out, &mut self.int.user_dict, &self.io.tasks);
-            match self.servers.get3() {
-                Messages::Unloaded(_) => {
-                    panic!("this should never happen!");
-                }
-                Messages::Loaded(_) => {}
-                _ => {
-                    self.int.grid.context = Context::Channel;
-                    self.reset_all();
-                    return;
+    /// Writes the current code block to a file under `output/`, optionally opening it afterwards.
+    fn save_code_block(&mut self, open: bool) {
+        let loaded = self.servers.get3().assume_loaded();
+        let (pos, lang, code) = match loaded.code_block(&self.int.grid, &self.int.user_dict) {
+            Some(val) => val,
+            None => return self.notice("There's no code block in this message.".to_string()),
+        };
+        let name = format!("code-{}-{}.{}", loaded.labels[loaded.current].id.0, pos + 1, highlight::extension(&lang));
+        let (path, _) = fs_write(&name);
+        fs_write_2(code.into_bytes(), &name);
+        if open {
+            self.int.file_options.open(&path);
+        } else {
+            self.notice(format!("Saved the code block to {}", path));
+        }
+    }
+    /// Publishes the current message of a news channel to the channels following it.
+    fn crosspost(&mut self) {
+        let (channel, message) = match self.servers.get3() {
+            Messages::Loaded(val) => match (&val.id, val.labels.get(val.current)) {
+                (Channel::Guild(ch), Some(msg)) if ch.kind == ChannelType::News => (ch.id, msg.id),
+                (_, Some(_)) => return self.notice("Only messages in news channels can be published.".to_string()),
+                (_, None) => return,
+            },
+            _ => return,
+        };
+        match block_on(self.http().crosspost_message(channel.0, message.0)) {
+            Ok(_) => self.notice("Published the message.".to_string()),
+            Err(why) => self.notice(why.to_string()),
+        }
+    }
+    pub fn parse_bookmark(&mut self, input: KeyEvent) {
+        let KeyEvent { code, modifiers: _ } = input;
+        match code {
+            KeyCode::Esc => {
+                self.int.state = State::None;
+                self.reset_all();
+            }
+            KeyCode::Enter => {
+                let text = self.prompt_box.flush();
+                self.int.state = State::None;
+                self.reset_all();
+                let name = if text.trim().is_empty() {"bookmark".to_string()} else {text.trim().to_string()};
+                self.servers.get3().assume_loaded().set_bookmark(Some(name));
+            }
+            KeyCode::Backspace => {
+                self.prompt_box.backspace();
+            }
+            KeyCode::Delete => {
+                self.prompt_box.delete();
+            }
+            KeyCode::Left => self.prompt_box.left(),
+            KeyCode::Right => self.prompt_box.right(),
+            KeyCode::Char(val) => self.prompt_box.add_char(val),
+            _ => {}
+        }
+    }
+    pub fn parse_date(&mut self, input: KeyEvent) {
+        let KeyEvent { code, modifiers: _ } = input;
+        match code {
+            KeyCode::Esc => {
+                self.int.state = State::None;
+                self.reset_all();
+            }
+            KeyCode::Enter => {
+                let text = self.prompt_box.flush();
+                self.int.state = State::None;
+                self.reset_all();
+                let date = match NaiveDate::parse_from_str(text.trim(), "%Y-%m-%d") {
+                    Ok(val) => Local.from_local_date(&val).earliest().map(|x| x.and_hms(0, 0, 0)),
+                    Err(_) => None,
+                };
+                let date = match date {
+                    Some(val) => val,
+                    None => return self.notice(format!("{} isn't a date like 2021-06-30.", text)),
+                };
+                let loaded = self.servers.get3().assume_loaded();
+                if !loaded.jump_to_date(date) {
+                    if loaded.load_history(&self.io.tasks, Until::Date(date)) {
+                        loaded.target = Some(date);
+                        self.notice(format!("Loading messages back to {}.", text.trim()));
+                    } else {
+                        self.notice("There are no older messages to load.".to_string());
+                    }
                 }
             }
+            KeyCode::Backspace => {
+                self.prompt_box.backspace();
+            }
+            KeyCode::Delete => {
+                self.prompt_box.delete();
+            }
+            KeyCode::Left => self.prompt_box.left(),
+            KeyCode::Right => self.prompt_box.right(),
+            KeyCode::Char(val) => self.prompt_box.add_char(val),
+            _ => {}
+        }
+    }
+    pub fn parse_search(&mut self, input: KeyEvent) {
+        let KeyEvent { code, modifiers: _ } = input;
         match code {
-            KeyCode::Backspace | KeyCode::Esc | KeyCode::Enter => self.int.state = State::None,
-            KeyCode::Left => {
+            KeyCode::Esc => {
+                self.int.state = State::None;
+                self.message_box.flag();
+            }
+            KeyCode::Enter => {
+                self.int.state = State::None;
+                self.message_box.flag();
+                let query = self.prompt_box.flush();
+                if let Messages::Loaded(val) = self.servers.get3() {
+                    val.start_search(&query);
+                    // only what's already loaded is searched until n is pressed
+                    let res = val.search_older(&self.io.tasks, false);
+                    self.search_notice(res);
+                }
+            }
+            KeyCode::Backspace => {
+                self.prompt_box.backspace();
+            }
+            KeyCode::Delete => {
+                self.prompt_box.delete();
+            }
+            KeyCode::Left => self.prompt_box.left(),
+            KeyCode::Right => self.prompt_box.right(),
+            KeyCode::Char(val) => self.prompt_box.add_char(val),
+            _ => {}
+        }
+    }
+    fn search_notice(&mut self, res: SearchResult) {
+        match res {
+            SearchResult::Found => {}
+            SearchResult::Searching => self.notice("Searching older messages...".to_string()),
+            SearchResult::Missing => self.notice("No more matches.".to_string()),
+        }
+    }
+    pub fn parse_visual_messages(&mut self, action: Action) {
+        match action {
+            Action::ExitVisual => self.int.state = State::None,
+            Action::FocusLeft => {
                 self.int.grid.context = Context::Channel;
                 self.servers.get3().assume_loaded().flag();
                 self.servers.get2().flag();
             }
-            KeyCode::Up => {
-                self.servers.get3().assume_loaded().up(&self.int.grid);
-            }
-            KeyCode::Down => {
-                self.servers.get3().assume_loaded().down(&self.int.grid);
-            }
-            KeyCode::Char('r') => self.servers.get3().assume_loaded().red(&mut self.int.user_dict),
-            KeyCode::Char('g') => self.servers.get3().assume_loaded().green(&mut self.int.user_dict),
-            KeyCode::Char('b') => self.servers.get3().assume_loaded().blue(&mut self.int.user_dict),
+            Action::Red => self.servers.get3().assume_loaded().red(&mut self.int.user_dict),
+            Action::Green => self.servers.get3().assume_loaded().green(&mut self.int.user_dict),
+            Action::Blue => self.servers.get3().assume_loaded().blue(&mut self.int.user_dict),
             _ => {}
         }
     }
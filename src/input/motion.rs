@@ -0,0 +1,184 @@
+use crossterm::event::{KeyCode, KeyEvent};
+
+use crate::{keymap::Action, messages::Messages};
+
+use super::Context;
+
+const MAX_COUNT: usize = 9999;
+
+/// A cursor movement, which can be repeated by typing a count before it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    HalfUp,
+    HalfDown,
+    Top,
+    Bottom,
+}
+impl Motion {
+    pub fn from_action(action: Action) -> Option<Motion> {
+        match action {
+            Action::Up => Some(Motion::Up),
+            Action::Down => Some(Motion::Down),
+            Action::PageUp => Some(Motion::PageUp),
+            Action::PageDown => Some(Motion::PageDown),
+            Action::HalfPageUp => Some(Motion::HalfUp),
+            Action::HalfPageDown => Some(Motion::HalfDown),
+            Action::Top => Some(Motion::Top),
+            Action::Bottom => Some(Motion::Bottom),
+            _ => None,
+        }
+    }
+    fn upwards(self) -> bool {
+        matches!(self, Motion::Up | Motion::PageUp | Motion::HalfUp | Motion::Top)
+    }
+    /// How many rows the motion covers in a pane of the given height.
+    fn rows(self, count: usize, height: usize) -> usize {
+        match self {
+            Motion::Up | Motion::Down => count,
+            Motion::PageUp | Motion::PageDown => count * height.max(1),
+            Motion::HalfUp | Motion::HalfDown => count * (height / 2).max(1),
+            Motion::Top | Motion::Bottom => usize::MAX,
+        }
+    }
+}
+/// Where the cursor of a list ends up after a motion.
+fn step(current: usize, len: usize, rows: usize, up: bool) -> usize {
+    if up {
+        current.saturating_sub(rows)
+    } else {
+        current.saturating_add(rows).min(len.saturating_sub(1))
+    }
+}
+
+impl super::Parser {
+    /// Handles digits typed as a count for the next motion.
+    /// Returns false if the key isn't one, leaving it to the keymap.
+    pub fn parse_count(&mut self, input: KeyEvent) -> bool {
+        if let KeyCode::Char(val) = input.code {
+            // a lone 0 isn't a count
+            if val.is_ascii_digit() && (val != '0' || self.int.count > 0) && input.modifiers.is_empty() {
+                let digit = val.to_digit(10).unwrap_or(0) as usize;
+                self.int.count = (self.int.count * 10 + digit).min(MAX_COUNT);
+                return true;
+            }
+        }
+        false
+    }
+    /// Moves the cursor of a pane, setting its flag at most once so it's only redrawn once.
+    pub fn apply_motion(&mut self, pane: Context, motion: Motion, count: usize) {
+        let height = self.int.grid.height();
+        let rows = motion.rows(count, height);
+        let up = motion.upwards();
+        match pane {
+            Context::Server => {
+                let servers = &mut self.servers;
+                let target = step(servers.current, servers.labels.len(), rows, up);
+                if target != servers.current {
+                    servers.current = target;
+                    servers.flag();
+                }
+            }
+            Context::Category => {
+                let categories = self.servers.get();
+                let target = step(categories.current, categories.labels.len(), rows, up);
+                if target != categories.current {
+                    categories.current = target;
+                    categories.flag();
+                }
+            }
+            Context::Channel => {
+                let channels = self.servers.get2();
+                let target = step(channels.current, channels.labels.len(), rows, up);
+                if target != channels.current {
+                    channels.current = target;
+                    channels.flag();
+                }
+            }
+            Context::Message => {
+                let loaded = match self.servers.get3() {
+                    Messages::Loaded(val) => val,
+                    _ => return,
+                };
+                let (grid, dict) = (&self.int.grid, &self.int.user_dict);
+                match motion {
+                    Motion::Top => loaded.ctrl_up(),
+                    Motion::Bottom => loaded.jump_to_present(grid, dict, &self.io.tasks),
+                    _ => {
+                        // lines of a message are rows here, so there's no telling where the cursor ends up without moving it
+                        for _ in 0..rows {
+                            let before = (loaded.current, loaded.current_in_message);
+                            if up {
+                                loaded.up(grid, dict);
+                            } else {
+                                loaded.down(grid, dict);
+                            }
+                            if (loaded.current, loaded.current_in_message) == before {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_clamp_at_the_top() {
+        assert_eq!(step(3, 10, 5, true), 0);
+        assert_eq!(step(0, 10, 1, true), 0);
+        assert_eq!(step(7, 10, 2, true), 5);
+    }
+
+    #[test]
+    fn counts_clamp_at_the_bottom() {
+        assert_eq!(step(3, 10, 50, false), 9);
+        assert_eq!(step(9, 10, 1, false), 9);
+        assert_eq!(step(2, 10, 3, false), 5);
+    }
+
+    #[test]
+    fn empty_lists_stay_at_the_start() {
+        assert_eq!(step(0, 0, 1, false), 0);
+        assert_eq!(step(0, 0, 1, true), 0);
+    }
+
+    #[test]
+    fn top_and_bottom_reach_the_ends() {
+        let rows = Motion::Bottom.rows(1, 20);
+        assert_eq!(step(4, 100, rows, Motion::Bottom.upwards()), 99);
+        let rows = Motion::Top.rows(3, 20);
+        assert_eq!(step(42, 100, rows, Motion::Top.upwards()), 0);
+    }
+
+    #[test]
+    fn pages_scale_with_the_count_and_height() {
+        assert_eq!(Motion::Down.rows(3, 20), 3);
+        assert_eq!(Motion::PageDown.rows(2, 20), 40);
+        assert_eq!(Motion::HalfUp.rows(2, 20), 20);
+    }
+
+    #[test]
+    fn tiny_panes_still_move() {
+        assert_eq!(Motion::PageUp.rows(1, 0), 1);
+        assert_eq!(Motion::HalfDown.rows(1, 1), 1);
+    }
+
+    #[test]
+    fn every_motion_has_an_action() {
+        let actions = [Action::Up, Action::Down, Action::PageUp, Action::PageDown, Action::HalfPageUp, Action::HalfPageDown, Action::Top, Action::Bottom];
+        for action in actions.iter() {
+            let motion = Motion::from_action(*action).expect("not a motion");
+            let up = matches!(action, Action::Up | Action::PageUp | Action::HalfPageUp | Action::Top);
+            assert_eq!(motion.upwards(), up);
+        }
+        assert!(Motion::from_action(Action::Select).is_none());
+    }
+}
@@ -0,0 +1,52 @@
+use crossterm::event::{MouseButton, MouseEvent, MouseEventKind};
+
+use crate::messages::Messages;
+
+use super::{Context, State, motion::Motion};
+
+const SCROLL_ROWS: usize = 3; // how far one notch of the wheel moves
+
+impl super::Parser {
+    /// Clicks focus the pane under the cursor and move to the row clicked, and the wheel scrolls it.
+    /// The mouse is ignored while typing, since the panes may be covered.
+    pub fn parse_mouse(&mut self, event: MouseEvent) {
+        if self.int.state != State::None && self.int.state != State::Visual {
+            return;
+        }
+        let (x, y) = (event.column as usize, event.row as usize);
+        if y < self.int.grid.start_y || y >= self.int.grid.border_y || x >= self.int.grid.end_x {
+            return;
+        }
+        let pane = self.int.grid.pane_at(x);
+        let row = y - self.int.grid.start_y;
+        match event.kind {
+            MouseEventKind::Down(MouseButton::Left) => self.click(pane, row),
+            MouseEventKind::ScrollUp => self.apply_motion(pane, Motion::Up, SCROLL_ROWS),
+            MouseEventKind::ScrollDown => self.apply_motion(pane, Motion::Down, SCROLL_ROWS),
+            _ => {}
+        }
+    }
+    fn click(&mut self, pane: Context, row: usize) {
+        self.int.count = 0;
+        if pane != self.int.grid.context {
+            self.int.grid.context = pane;
+            self.reset_all();
+        }
+        let height = self.int.grid.height();
+        match pane {
+            Context::Server => self.servers.click(row, height),
+            Context::Category => self.servers.get().click(row, height),
+            Context::Channel => self.servers.get2().click(row, height),
+            Context::Message => {
+                if !self.prepare_messages() {
+                    return;
+                }
+                if let Messages::Loaded(val) = self.servers.get3() {
+                    if let Some(path) = val.click(row, &self.int.grid, &self.int.user_dict) {
+                        self.int.file_options.open(&path);
+                    }
+                }
+            }
+        }
+    }
+}
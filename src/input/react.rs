@@ -0,0 +1,77 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use serenity::model::channel::ReactionType;
+
+use crate::{block_on::block_on, emoji, messages::Messages};
+
+use super::State;
+
+const RECENT_LEN: usize = 9;
+
+impl super::Parser {
+    pub fn parse_react(&mut self, input: KeyEvent) {
+        let KeyEvent { code, modifiers: _ } = input;
+        match code {
+            KeyCode::Esc => {
+                self.int.state = State::None;
+                self.reset_all();
+            }
+            // recent emoji can be picked by number
+            KeyCode::Char(val) if val.is_ascii_digit() && self.prompt_box.text().is_empty() => {
+                let pos = val.to_digit(10).unwrap_or(0) as usize;
+                if let Some(kind) = pos.checked_sub(1).and_then(|x| self.int.recent_emoji.get(x)).cloned() {
+                    self.int.state = State::None;
+                    self.reset_all();
+                    self.toggle_reaction(kind);
+                } else {
+                    self.prompt_box.add_char(val);
+                }
+            }
+            KeyCode::Enter => {
+                let text = self.prompt_box.flush();
+                self.int.state = State::None;
+                self.reset_all();
+                // custom emojis are looked up with the rest of the server's info
+                let guild = self.servers.get3().id().and_then(|x| x.guild()).map(|x| x.guild_id);
+                let emojis = guild.and_then(|x| self.int.emojis.get(&x)).map(|x| x.as_slice()).unwrap_or(&[]);
+                match emoji::parse(&text, emojis) {
+                    Some(kind) => self.toggle_reaction(kind),
+                    None => self.notice(format!("Could not find the emoji {}!", text)),
+                }
+            }
+            KeyCode::Backspace => {
+                self.prompt_box.backspace();
+            }
+            KeyCode::Delete => {
+                self.prompt_box.delete();
+            }
+            KeyCode::Left => self.prompt_box.left(),
+            KeyCode::Right => self.prompt_box.right(),
+            KeyCode::Char(val) => self.prompt_box.add_char(val),
+            _ => {}
+        }
+    }
+    /// Adds the reaction to the current message, or removes it if the user has already reacted with it.
+    /// The local counts are updated once the gateway echoes the change back.
+    fn toggle_reaction(&mut self, kind: ReactionType) {
+        let (channel, message, me) = match self.servers.get3() {
+            Messages::Loaded(val) => match val.labels.get(val.current) {
+                Some(msg) => (val.id.id(), msg.id, msg.reactions.iter().any(|x| x.kind == kind && x.me)),
+                None => return,
+            },
+            _ => return,
+        };
+        let result = if me {
+            block_on(self.http().delete_reaction(channel.0, message.0, None, &kind))
+        } else {
+            block_on(self.http().create_reaction(channel.0, message.0, &kind))
+        };
+        match result {
+            Ok(()) => {
+                self.int.recent_emoji.retain(|x| *x != kind);
+                self.int.recent_emoji.insert(0, kind);
+                self.int.recent_emoji.truncate(RECENT_LEN);
+            }
+            Err(why) => self.notice(why.to_string()),
+        }
+    }
+}
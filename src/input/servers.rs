@@ -1,56 +1,42 @@
-use crossterm::event::{KeyCode, KeyEvent};
+use crate::keymap::Action;
 
 use super::State;
 
 impl super::Parser {
-    pub fn parse_none_server(&mut self, input: KeyEvent) {
-        let KeyEvent { code, modifiers: _ } = input;
-        match code {
-            KeyCode::Right => {
+    pub fn parse_none_server(&mut self, action: Action) {
+        match action {
+            Action::FocusRight => {
                 self.int.grid.context = super::Context::Category;
                 self.servers.flag();
                 self.servers.get().flag();
             }
-            KeyCode::Up => {
-                self.servers.up();
-            }
-            KeyCode::Down => {
-                self.servers.down();
-            }
-            KeyCode::Enter => {
+            Action::Select => {
                 self.servers.select();
                 self.reset_all();
             }
-            KeyCode::Char('t') => {
-                self.int.state = State::Message;
+            Action::Type => {
+                self.start_message();
             }
-            KeyCode::Char('q') => {
+            Action::Quit => {
                 self.int.state = State::Quit;
                 self.parse_quit_start();
             }
             _ => {}
         }
     }
-    pub fn parse_visual_servers(&mut self, input: KeyEvent) {
-        let KeyEvent {code, ..} = input;
-        match code {
-            KeyCode::Backspace | KeyCode::Delete | KeyCode::Esc => self.int.state = State::None,
-            KeyCode::Right => {
+    pub fn parse_visual_servers(&mut self, action: Action) {
+        match action {
+            Action::ExitVisual => self.int.state = State::None,
+            Action::FocusRight => {
                 self.int.grid.context = super::Context::Category;
                 self.servers.flag();
                 self.servers.get().flag();
             }
-            KeyCode::Up => {
-                self.servers.up();
-            }
-            KeyCode::Down => {
-                self.servers.down();
-            }
-            KeyCode::Enter => {
+            Action::Select => {
                 self.servers.select();
                 self.reset_all();
             }
-            KeyCode::Char('c') => {
+            Action::Color => {
                 self.servers.color();
             },
             _ => {}
@@ -0,0 +1,499 @@
+use std::{fs, path::PathBuf};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::{input::Context, save::SAVE_DIR};
+
+pub const PATH: &str = "keymap.json";
+
+/// Something a key can be bound to.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    HalfPageUp,
+    HalfPageDown,
+    Top,
+    Bottom,
+    FocusLeft,
+    FocusRight,
+    Select,
+    Type,
+    Quit,
+    Filter,
+    GlobalSearch,
+    Help,
+    WidenPane,
+    NarrowPane,
+    ToggleServers,
+    ToggleCategories,
+    ToggleFocus,
+    ResetLayout,
+    EnterVisual,
+    ExitVisual,
+    Color,
+    SelectColor,
+    Back,
+    MessagePerson,
+    CycleRevision,
+    ToggleDeleted,
+    ToggleSpoilers,
+    JumpToMention,
+    OpenThread,
+    SaveCode,
+    OpenCode,
+    Crosspost,
+    Edit,
+    Reply,
+    QuoteReply,
+    React,
+    Search,
+    SearchOlder,
+    SearchNewer,
+    Bookmark,
+    Bookmarks,
+    JumpToDate,
+    LoadHistory,
+    OpenAttachment,
+    Red,
+    Green,
+    Blue,
+}
+impl Action {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Action::Up => "move up",
+            Action::Down => "move down",
+            Action::PageUp => "move up a page",
+            Action::PageDown => "move down a page",
+            Action::HalfPageUp => "move up half a page",
+            Action::HalfPageDown => "move down half a page",
+            Action::Top => "go to the top",
+            Action::Bottom => "go to the bottom",
+            Action::FocusLeft => "focus the pane to the left",
+            Action::FocusRight => "focus the pane to the right",
+            Action::Select => "select",
+            Action::Type => "type a message",
+            Action::Quit => "quit",
+            Action::Filter => "find a server, category or channel",
+            Action::GlobalSearch => "search every loaded message",
+            Action::Help => "list these bindings",
+            Action::WidenPane => "widen this pane",
+            Action::NarrowPane => "narrow this pane",
+            Action::ToggleServers => "show or hide the servers",
+            Action::ToggleCategories => "show or hide the categories",
+            Action::ToggleFocus => "show only the messages, or every pane",
+            Action::ResetLayout => "reset the pane sizes",
+            Action::EnterVisual => "enter visual mode",
+            Action::ExitVisual => "leave visual mode",
+            Action::Color => "change the color",
+            Action::SelectColor => "change the selection color",
+            Action::Back => "go back to the selected message",
+            Action::MessagePerson => "message the author",
+            Action::CycleRevision => "show another revision",
+            Action::ToggleDeleted => "show or hide deleted messages",
+            Action::ToggleSpoilers => "show or hide spoilers",
+            Action::JumpToMention => "go to the mentioned channel",
+            Action::OpenThread => "open the thread",
+            Action::SaveCode => "save the code block",
+            Action::OpenCode => "save and open the code block",
+            Action::Crosspost => "publish to followers",
+            Action::Edit => "edit the message",
+            Action::Reply => "reply",
+            Action::QuoteReply => "reply with a quote",
+            Action::React => "react",
+            Action::Search => "search this channel",
+            Action::SearchOlder => "next older match",
+            Action::SearchNewer => "next newer match",
+            Action::Bookmark => "bookmark the message, or remove its bookmark",
+            Action::Bookmarks => "list bookmarks",
+            Action::JumpToDate => "jump to a date",
+            Action::LoadHistory => "load older messages",
+            Action::OpenAttachment => "open the attachment",
+            Action::Red => "change the author's red",
+            Action::Green => "change the author's green",
+            Action::Blue => "change the author's blue",
+        }
+    }
+}
+/// Which of the two command states a binding applies in.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    Normal,
+    Visual,
+}
+/// Which pane a binding applies in.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Pane {
+    All,
+    Server,
+    Category,
+    Channel,
+    Message,
+}
+impl Pane {
+    /// Whether a binding in this pane is active everywhere one in the other pane is.
+    fn covers(self, other: Pane) -> bool {
+        self == Pane::All || self == other
+    }
+    fn contains(self, context: &Context) -> bool {
+        matches!(
+            (self, context),
+            (Pane::All, _)
+                | (Pane::Server, Context::Server)
+                | (Pane::Category, Context::Category)
+                | (Pane::Channel, Context::Channel)
+                | (Pane::Message, Context::Message)
+        )
+    }
+}
+/// A key, written like `t`, `T`, `Enter` or `ctrl+k`, bound to an action.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct Binding {
+    pub mode: Mode,
+    pub pane: Pane,
+    pub key: String,
+    pub action: Action,
+}
+pub struct Keymap {
+    pub bindings: Vec<Binding>,
+    keys: Vec<Option<(KeyCode, KeyModifiers)>>, // the parsed key of each binding
+    pub error: Option<String>, // why the file couldn't be used, shown once the screen is up
+}
+impl Keymap {
+    /// Loads the keymap from `save/keymap.json`, writing the defaults there if it doesn't exist yet.
+    /// The file's bindings are laid over the defaults, so actions added later still get their keys.
+    /// A file that can't be parsed is left alone for the user to fix, and the defaults are used instead.
+    pub fn new() -> Keymap {
+        let mut path = PathBuf::new();
+        path.push(SAVE_DIR);
+        path.push(PATH);
+        match fs::read(&path) {
+            Ok(val) => match serde_json::from_slice(&val) {
+                Ok(bindings) => Keymap::from_bindings(merge(bindings)),
+                Err(why) => {
+                    let mut keymap = Keymap::from_bindings(defaults());
+                    keymap.error = Some(format!("Couldn't read {}, using the default keys: {}", path.display(), why));
+                    keymap
+                }
+            },
+            Err(_) => {
+                let bindings = defaults();
+                if let Ok(val) = serde_json::to_string_pretty(&bindings) {
+                    let _ = fs::write(&path, val);
+                }
+                Keymap::from_bindings(bindings)
+            }
+        }
+    }
+    fn from_bindings(bindings: Vec<Binding>) -> Keymap {
+        let keys: Vec<_> = bindings.iter().map(|x| parse_key(&x.key)).collect();
+        let unreadable: Vec<&str> = bindings
+            .iter()
+            .zip(keys.iter())
+            .filter(|(_, key)| key.is_none())
+            .map(|(x, _)| x.key.as_str())
+            .collect();
+        let error = if unreadable.is_empty() {
+            None
+        } else {
+            Some(format!("Couldn't read the keys {} in {}, so they aren't bound", unreadable.join(", "), PATH))
+        };
+        Keymap { bindings, keys, error }
+    }
+    /// The action bound to a key in the focused pane, preferring bindings made for that pane.
+    pub fn get(&self, mode: Mode, context: &Context, input: KeyEvent) -> Option<Action> {
+        let key = normalize(input);
+        let mut found = None;
+        for (binding, parsed) in self.bindings.iter().zip(self.keys.iter()) {
+            if binding.mode != mode || *parsed != Some(key) || !binding.pane.contains(context) {
+                continue;
+            }
+            if binding.pane != Pane::All {
+                return Some(binding.action);
+            }
+            found = found.or(Some(binding.action));
+        }
+        found
+    }
+    /// Every binding active in the focused pane, leaving out those shadowed by a pane's own bindings.
+    pub fn active(&self, mode: Mode, context: &Context) -> Vec<&Binding> {
+        let mut result: Vec<&Binding> = Vec::new();
+        for (binding, parsed) in self.bindings.iter().zip(self.keys.iter()) {
+            if binding.mode != mode || !binding.pane.contains(context) {
+                continue;
+            }
+            if let Some(key) = parsed {
+                let input = KeyEvent::new(key.0, key.1);
+                if self.get(mode, context, input) == Some(binding.action) && !result.iter().any(|x| x.key == binding.key) {
+                    result.push(binding);
+                }
+            }
+        }
+        result
+    }
+}
+/// Adds each default binding unless the file already binds its action or its key in the same mode and pane.
+fn merge(bindings: Vec<Binding>) -> Vec<Binding> {
+    let mut result = bindings.clone();
+    for binding in defaults() {
+        let key = parse_key(&binding.key);
+        let replaced = bindings.iter().any(|x| {
+            x.mode == binding.mode
+                && x.pane.covers(binding.pane)
+                && (x.action == binding.action || parse_key(&x.key) == key)
+        });
+        if !replaced {
+            result.push(binding);
+        }
+    }
+    result
+}
+/// Shift is already part of the character, and isn't reported the same way by every terminal.
+fn normalize(input: KeyEvent) -> (KeyCode, KeyModifiers) {
+    (input.code, input.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT))
+}
+fn parse_key(spec: &str) -> Option<(KeyCode, KeyModifiers)> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = spec;
+    loop {
+        if let Some(val) = rest.strip_prefix("ctrl+") {
+            modifiers |= KeyModifiers::CONTROL;
+            rest = val;
+        } else if let Some(val) = rest.strip_prefix("alt+") {
+            modifiers |= KeyModifiers::ALT;
+            rest = val;
+        } else {
+            break;
+        }
+    }
+    let code = match rest {
+        "Left" => KeyCode::Left,
+        "Right" => KeyCode::Right,
+        "Up" => KeyCode::Up,
+        "Down" => KeyCode::Down,
+        "Home" => KeyCode::Home,
+        "End" => KeyCode::End,
+        "PageUp" => KeyCode::PageUp,
+        "PageDown" => KeyCode::PageDown,
+        "Enter" => KeyCode::Enter,
+        "Esc" => KeyCode::Esc,
+        "Backspace" => KeyCode::Backspace,
+        "Delete" => KeyCode::Delete,
+        "Tab" => KeyCode::Tab,
+        "Space" => KeyCode::Char(' '),
+        _ => {
+            let mut chars = rest.chars();
+            match (chars.next(), chars.next()) {
+                (Some(val), None) => KeyCode::Char(val),
+                _ => return None,
+            }
+        }
+    };
+    Some((code, modifiers))
+}
+/// The keymap used when there's no file, matching the keys from before keymaps existed.
+pub fn defaults() -> Vec<Binding> {
+    use Action::*;
+    use Mode::*;
+    use Pane::*;
+    let table: &[(Mode, Pane, &str, Action)] = &[
+        (Normal, All, "ctrl+k", Filter),
+        (Normal, All, "ctrl+f", GlobalSearch),
+        (Normal, All, "?", Help),
+        (Visual, All, "?", Help),
+        (Normal, All, ">", WidenPane),
+        (Normal, All, "<", NarrowPane),
+        (Normal, All, "{", ToggleServers),
+        (Normal, All, "}", ToggleCategories),
+        (Normal, All, "Z", ToggleFocus),
+        (Normal, All, "=", ResetLayout),
+        (Normal, All, "Up", Up),
+        (Normal, All, "k", Up),
+        (Normal, All, "Down", Down),
+        (Normal, All, "j", Down),
+        (Normal, All, "PageUp", PageUp),
+        (Normal, All, "PageDown", PageDown),
+        (Normal, All, "ctrl+u", HalfPageUp),
+        (Normal, All, "ctrl+d", HalfPageDown),
+        (Normal, All, "Home", Top),
+        (Normal, All, "End", Bottom),
+        (Visual, All, "Up", Up),
+        (Visual, All, "k", Up),
+        (Visual, All, "Down", Down),
+        (Visual, All, "j", Down),
+        (Visual, All, "PageUp", PageUp),
+        (Visual, All, "PageDown", PageDown),
+        (Visual, All, "ctrl+u", HalfPageUp),
+        (Visual, All, "ctrl+d", HalfPageDown),
+        (Visual, All, "Home", Top),
+        (Visual, All, "End", Bottom),
+        (Normal, Server, "Right", FocusRight),
+        (Normal, Server, "Enter", Select),
+        (Normal, Server, "t", Type),
+        (Normal, Server, "q", Quit),
+        (Visual, Server, "Backspace", ExitVisual),
+        (Visual, Server, "Delete", ExitVisual),
+        (Visual, Server, "Esc", ExitVisual),
+        (Visual, Server, "Right", FocusRight),
+        (Visual, Server, "Enter", Select),
+        (Visual, Server, "c", Color),
+        (Normal, Category, "Left", FocusLeft),
+        (Normal, Category, "Right", FocusRight),
+        (Normal, Category, "Enter", Select),
+        (Normal, Category, "t", Type),
+        (Normal, Category, "f", Filter),
+        (Visual, Category, "Backspace", ExitVisual),
+        (Visual, Category, "Delete", ExitVisual),
+        (Visual, Category, "Esc", ExitVisual),
+        (Visual, Category, "Left", FocusLeft),
+        (Visual, Category, "Right", FocusRight),
+        (Visual, Category, "Enter", Select),
+        (Visual, Category, "c", Color),
+        (Normal, Channel, "Left", FocusLeft),
+        (Normal, Channel, "Right", FocusRight),
+        (Normal, Channel, "Enter", Select),
+        (Normal, Channel, "t", Type),
+        (Visual, Channel, "Left", FocusLeft),
+        (Visual, Channel, "Right", FocusRight),
+        (Visual, Channel, "Enter", Select),
+        (Visual, Channel, "c", Color),
+        (Visual, Channel, "s", SelectColor),
+        (Visual, Channel, "Esc", ExitVisual),
+        (Visual, Channel, "Delete", ExitVisual),
+        (Visual, Channel, "Backspace", ExitVisual),
+        (Normal, Message, "Left", FocusLeft),
+        (Normal, Message, "Enter", Select),
+        (Normal, Message, "t", Type),
+        (Normal, Message, "b", Back),
+        (Normal, Message, "v", EnterVisual),
+        (Normal, Message, "m", MessagePerson),
+        (Normal, Message, "h", CycleRevision),
+        (Normal, Message, "x", ToggleDeleted),
+        (Normal, Message, "s", ToggleSpoilers),
+        (Normal, Message, "g", JumpToMention),
+        (Normal, Message, "T", OpenThread),
+        (Normal, Message, "y", SaveCode),
+        (Normal, Message, "O", OpenCode),
+        (Normal, Message, "P", Crosspost),
+        (Normal, Message, "e", Edit),
+        (Normal, Message, "r", Reply),
+        (Normal, Message, "R", QuoteReply),
+        (Normal, Message, "+", React),
+        (Normal, Message, "/", Search),
+        (Normal, Message, "n", SearchOlder),
+        (Normal, Message, "N", SearchNewer),
+        (Normal, Message, "M", Bookmark),
+        (Normal, Message, "'", Bookmarks),
+        (Normal, Message, "J", JumpToDate),
+        (Normal, Message, "L", LoadHistory),
+        (Normal, Message, "o", OpenAttachment),
+        (Visual, Message, "Backspace", ExitVisual),
+        (Visual, Message, "Esc", ExitVisual),
+        (Visual, Message, "Enter", ExitVisual),
+        (Visual, Message, "Left", FocusLeft),
+        (Visual, Message, "r", Red),
+        (Visual, Message, "g", Green),
+        (Visual, Message, "b", Blue),
+    ];
+    table
+        .iter()
+        .map(|&(mode, pane, key, action)| Binding { mode, pane, key: key.to_string(), action })
+        .collect()
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(mode: Mode, pane: Pane, key: &str, action: Action) -> Binding {
+        Binding { mode, pane, key: key.to_string(), action }
+    }
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+    fn has(bindings: &[Binding], pane: Pane, key: &str, action: Action) -> bool {
+        bindings.iter().any(|x| x.mode == Mode::Normal && x.pane == pane && x.key == key && x.action == action)
+    }
+
+    #[test]
+    fn keys_are_parsed_with_their_modifiers() {
+        assert_eq!(parse_key("t"), Some((KeyCode::Char('t'), KeyModifiers::NONE)));
+        assert_eq!(parse_key("Enter"), Some((KeyCode::Enter, KeyModifiers::NONE)));
+        assert_eq!(parse_key("Space"), Some((KeyCode::Char(' '), KeyModifiers::NONE)));
+        assert_eq!(parse_key("ctrl+k"), Some((KeyCode::Char('k'), KeyModifiers::CONTROL)));
+        assert_eq!(parse_key("alt+ctrl+Up"), Some((KeyCode::Up, KeyModifiers::CONTROL | KeyModifiers::ALT)));
+    }
+
+    #[test]
+    fn unreadable_keys_are_reported() {
+        assert_eq!(parse_key("ctrl+"), None);
+        assert_eq!(parse_key("Enterr"), None);
+        let keymap = Keymap::from_bindings(vec![binding(Mode::Normal, Pane::All, "shift+x", Action::Quit)]);
+        assert!(keymap.error.map(|x| x.contains("shift+x")).unwrap_or(false));
+        assert!(Keymap::from_bindings(defaults()).error.is_none());
+    }
+
+    #[test]
+    fn shift_is_part_of_the_character() {
+        let keymap = Keymap::from_bindings(defaults());
+        let input = KeyEvent::new(KeyCode::Char('T'), KeyModifiers::SHIFT);
+        assert!(keymap.get(Mode::Normal, &Context::Message, input) == Some(Action::OpenThread));
+    }
+
+    #[test]
+    fn pane_bindings_win_over_global_ones() {
+        let keymap = Keymap::from_bindings(vec![
+            binding(Mode::Normal, Pane::Message, "x", Action::Down),
+            binding(Mode::Normal, Pane::All, "x", Action::Up),
+        ]);
+        let input = key(KeyCode::Char('x'));
+        assert!(keymap.get(Mode::Normal, &Context::Message, input) == Some(Action::Down));
+        assert!(keymap.get(Mode::Normal, &Context::Server, input) == Some(Action::Up));
+        assert!(keymap.get(Mode::Visual, &Context::Server, input).is_none());
+        let active = keymap.active(Mode::Normal, &Context::Message);
+        assert_eq!(active.len(), 1);
+        assert!(active[0].action == Action::Down);
+    }
+
+    #[test]
+    fn file_bindings_only_replace_defaults_in_their_pane() {
+        let merged = merge(vec![binding(Mode::Normal, Pane::Message, "Space", Action::Select)]);
+        assert!(!has(&merged, Pane::Message, "Enter", Action::Select));
+        assert!(has(&merged, Pane::Server, "Enter", Action::Select));
+        assert!(has(&merged, Pane::Channel, "Enter", Action::Select));
+    }
+
+    #[test]
+    fn file_keys_only_shadow_defaults_in_their_pane() {
+        let merged = merge(vec![binding(Mode::Normal, Pane::Message, "t", Action::Search)]);
+        assert!(!has(&merged, Pane::Message, "t", Action::Type));
+        assert!(!has(&merged, Pane::Message, "/", Action::Search));
+        assert!(has(&merged, Pane::Server, "t", Action::Type));
+        assert!(has(&merged, Pane::Category, "t", Action::Type));
+    }
+
+    #[test]
+    fn global_file_bindings_replace_defaults_everywhere() {
+        let merged = merge(vec![binding(Mode::Normal, Pane::All, "Q", Action::Quit), binding(Mode::Normal, Pane::All, "e", Action::Top)]);
+        assert!(!has(&merged, Pane::Server, "q", Action::Quit));
+        assert!(!has(&merged, Pane::Message, "e", Action::Edit));
+        assert!(!has(&merged, Pane::All, "Home", Action::Top));
+        // other modes are left alone
+        assert!(merged.iter().any(|x| x.mode == Mode::Visual && x.key == "Home" && x.action == Action::Top));
+    }
+
+    #[test]
+    fn new_actions_keep_their_default_keys() {
+        let merged = merge(vec![binding(Mode::Normal, Pane::All, "j", Action::Down)]);
+        assert!(has(&merged, Pane::All, "ctrl+k", Action::Filter));
+        assert!(has(&merged, Pane::Message, "o", Action::OpenAttachment));
+        // binding an action replaces all of its default keys
+        assert!(!has(&merged, Pane::All, "Down", Action::Down));
+        assert_eq!(merged.len(), defaults().len() - 1);
+    }
+}
@@ -3,6 +3,7 @@ use std::io::stdout;
 use std::sync::mpsc::channel;
 use std::sync::mpsc::Sender;
 
+use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
 use crossterm::execute;
 use crossterm::terminal;
 use crossterm::terminal::disable_raw_mode;
@@ -10,16 +11,25 @@ use crossterm::terminal::enable_raw_mode;
 use crossterm::terminal::ClearType;
 use input::Response;
 use serenity::framework::StandardFramework;
-use serenity::{async_trait, model::channel::Message, prelude::*};
+use serenity::{async_trait, model::{channel::{Channel, ChannelCategory, GuildChannel, Message, Reaction}, event::MessageUpdateEvent, gateway::Ready, guild::{Guild, GuildUnavailable, PartialGuild}, id::{ChannelId, GuildId, MessageId}, voice::VoiceState}, prelude::*};
 
 use crate::block_on::block_on;
 
 #[allow(dead_code)]
 mod ansi;
+mod api;
 pub mod categories;
 pub mod channels;
+mod emoji;
 mod file;
+mod filter;
 mod format;
+mod highlight;
+mod index;
+mod keymap;
+mod markdown;
+mod mention;
+mod permissions;
 pub mod render;
 mod input;
 mod message;
@@ -49,12 +59,142 @@ impl EventHandler for Handler {
             .expect("the receiver has hung up!");
     }
 
+    // Set a handler for the `message_update` event, which is dispatched
+    // whenever a message is edited. Only the raw event is forwarded, since
+    // the old message is kept in our own edit history anyway.
+    async fn message_update(&self, _: Context, _: Option<Message>, _: Option<Message>, event: MessageUpdateEvent) {
+        let sent = Mutex::lock(&self.send).await;
+        sent.send(Response::Edit(event))
+            .expect("the receiver has hung up!");
+    }
+
+    // Deletions only carry ids; the messages themselves are kept locally as
+    // tombstones, so single and bulk deletes are forwarded the same way.
+    async fn message_delete(&self, _: Context, channel: ChannelId, message: MessageId, guild: Option<GuildId>) {
+        let sent = Mutex::lock(&self.send).await;
+        sent.send(Response::Delete(channel, vec![message], guild))
+            .expect("the receiver has hung up!");
+    }
+
+    async fn message_delete_bulk(&self, _: Context, channel: ChannelId, messages: Vec<MessageId>, guild: Option<GuildId>) {
+        let sent = Mutex::lock(&self.send).await;
+        sent.send(Response::Delete(channel, messages, guild))
+            .expect("the receiver has hung up!");
+    }
+
+    // Sent once per guild on startup, along with everyone connected to voice,
+    // and again whenever a guild is joined or comes back from an outage.
+    async fn guild_create(&self, _: Context, guild: Guild, _: bool) {
+        let sent = Mutex::lock(&self.send).await;
+        let channels = guild.channels.into_iter().map(|x| x.1).collect();
+        sent.send(Response::Guild(guild.id, guild.name, Some(channels)))
+            .expect("the receiver has hung up!");
+        for (_, mut state) in guild.voice_states {
+            state.guild_id = Some(guild.id);
+            sent.send(Response::Voice(state))
+                .expect("the receiver has hung up!");
+        }
+    }
+
+    async fn guild_update(&self, _: Context, _: Option<Guild>, guild: PartialGuild) {
+        let sent = Mutex::lock(&self.send).await;
+        sent.send(Response::Guild(guild.id, guild.name, None))
+            .expect("the receiver has hung up!");
+    }
+
+    // Also sent when a guild becomes unavailable, which isn't the same as leaving it.
+    async fn guild_delete(&self, _: Context, guild: GuildUnavailable, _: Option<Guild>) {
+        let sent = Mutex::lock(&self.send).await;
+        sent.send(Response::GuildDelete(guild.id, guild.unavailable))
+            .expect("the receiver has hung up!");
+    }
+
+    // Categories have events of their own, but are handled like any other channel.
+    async fn channel_create(&self, _: Context, channel: &GuildChannel) {
+        let sent = Mutex::lock(&self.send).await;
+        sent.send(Response::ChannelUpdate(channel.clone()))
+            .expect("the receiver has hung up!");
+    }
+
+    async fn category_create(&self, _: Context, category: &ChannelCategory) {
+        if let Some(channel) = api::category(category) {
+            let sent = Mutex::lock(&self.send).await;
+            sent.send(Response::ChannelUpdate(channel))
+                .expect("the receiver has hung up!");
+        }
+    }
+
+    async fn channel_update(&self, _: Context, _: Option<Channel>, channel: Channel) {
+        let channel = match channel {
+            Channel::Guild(val) => val,
+            Channel::Category(val) => match api::category(&val) {
+                Some(val) => val,
+                None => return,
+            },
+            _ => return,
+        };
+        let sent = Mutex::lock(&self.send).await;
+        sent.send(Response::ChannelUpdate(channel))
+            .expect("the receiver has hung up!");
+    }
+
+    async fn channel_delete(&self, _: Context, channel: &GuildChannel) {
+        let sent = Mutex::lock(&self.send).await;
+        sent.send(Response::ChannelDelete(channel.clone()))
+            .expect("the receiver has hung up!");
+    }
+
+    async fn category_delete(&self, _: Context, category: &ChannelCategory) {
+        if let Some(channel) = api::category(category) {
+            let sent = Mutex::lock(&self.send).await;
+            sent.send(Response::ChannelDelete(channel))
+                .expect("the receiver has hung up!");
+        }
+    }
+
+    async fn voice_state_update(&self, _: Context, guild: Option<GuildId>, _: Option<VoiceState>, mut state: VoiceState) {
+        state.guild_id = state.guild_id.or(guild);
+        let sent = Mutex::lock(&self.send).await;
+        sent.send(Response::Voice(state))
+            .expect("the receiver has hung up!");
+    }
+
+    // This version of serenity doesn't know about threads, so their events
+    // arrive as unknown ones.
+    async fn unknown(&self, _: Context, name: String, raw: serde_json::Value) {
+        if name == "THREAD_CREATE" || name == "THREAD_UPDATE" {
+            if let Some(thread) = api::thread(raw) {
+                let sent = Mutex::lock(&self.send).await;
+                sent.send(Response::Thread(thread))
+                    .expect("the receiver has hung up!");
+            }
+        }
+    }
+
+    async fn reaction_add(&self, _: Context, reaction: Reaction) {
+        let sent = Mutex::lock(&self.send).await;
+        sent.send(Response::Reaction(reaction, true))
+            .expect("the receiver has hung up!");
+    }
+
+    async fn reaction_remove(&self, _: Context, reaction: Reaction) {
+        let sent = Mutex::lock(&self.send).await;
+        sent.send(Response::Reaction(reaction, false))
+            .expect("the receiver has hung up!");
+    }
+
     // Set a handler to be called on the `ready` event. This is called when a
     // shard is booted, and a READY payload is sent by Discord. This payload
     // contains data like the current user's guild Ids, current user data,
     // private channels, and more.
     //
-    // In this case, just print what the current user's username is.
+    // A new session means events may have been missed, so loaded channels are
+    // checked for newer messages.
+    async fn ready(&self, _: Context, _: Ready) {
+        let sent = Mutex::lock(&self.send).await;
+        sent.send(Response::Ready)
+            .expect("the receiver has hung up!");
+    }
 }
 fn get_token() -> String {
     if let Ok(val) = fs::read_to_string("token.ignore") {
@@ -65,6 +205,21 @@ fn get_token() -> String {
         res
     }
 }
+/// Puts the terminal back the way it was when dropped, even if the parser thread panicked.
+struct RawMode;
+impl RawMode {
+    fn enable() -> RawMode {
+        enable_raw_mode().expect("fatal error: ");
+        let _ = execute!(stdout(), EnableMouseCapture);
+        RawMode
+    }
+}
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = execute!(stdout(), DisableMouseCapture);
+        let _ = disable_raw_mode();
+    }
+}
 fn main() {
     let _ = std::fs::create_dir("output");
     let _ = std::fs::create_dir("save");
@@ -96,8 +251,9 @@ fn main() {
     let _ = execute!(stdout(), terminal::Clear(ClearType::All)).expect("fatal error: "); // clears the terminal
     let (tasks, controller, products) = crate::task::start(&token);
     let parser = input::Parser::new(recv, client2, tasks, controller, products);
-    enable_raw_mode().expect("fatal error: ");
+    let raw = RawMode::enable();
     let v = parser.start(); // starts on a new thread
-    v.join().expect("fatal error: ");
-    disable_raw_mode().expect("fatal error: ");
+    let result = v.join();
+    drop(raw);
+    result.expect("fatal error: ");
 }
@@ -0,0 +1,354 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{ansi, highlight, mention, message::UserDict};
+
+/// A run of text drawn in a single style.
+#[derive(Clone)]
+pub struct Span {
+    pub text: String,
+    pub style: String,
+}
+impl Span {
+    pub fn new(text: impl Into<String>, style: impl Into<String>) -> Span {
+        Span {
+            text: text.into(),
+            style: style.into(),
+        }
+    }
+}
+/// A rendered line of a message.
+pub struct Line {
+    pub spans: Vec<Span>,
+    pub block: Option<usize>, // the code block the line belongs to
+}
+impl Line {
+    pub fn new(spans: Vec<Span>) -> Line {
+        Line { spans, block: None }
+    }
+}
+const SPOILER: &str = "||";
+// longer markers have to come before their prefixes
+const MARKERS: &[(&str, &str)] = &[
+    ("**", ansi::HIGH_INTENSITY),
+    ("__", ansi::UNDERLINE),
+    ("~~", ansi::CROSSED_OUT),
+    (SPOILER, ""),
+    ("*", ansi::ITALIC),
+    ("_", ansi::ITALIC),
+];
+const QUOTE: &str = "▎ ";
+
+/// Renders the lines of a message's markdown into styled spans.
+/// Spoilers are blanked out unless `reveal` is set, and mentions are shown by name.
+pub fn render(lines: &[String], reveal: bool, dict: &UserDict) -> Vec<Line> {
+    let mut result = Vec::new();
+    let mut fence: Option<&str> = None; // the language of the code block the line is in
+    let mut blocks = 0;
+    let mut quote = false; // everything after `>>> ` is quoted
+    for line in lines {
+        if let Some(lang) = fence {
+            let block = Some(blocks - 1);
+            match line.trim_end().strip_suffix("```") {
+                Some(val) => {
+                    fence = None;
+                    if !val.is_empty() {
+                        result.push(Line { spans: highlight::highlight(val, lang), block });
+                    }
+                }
+                None => result.push(Line { spans: highlight::highlight(line, lang), block }),
+            }
+            continue;
+        }
+        if let Some(val) = line.strip_prefix("```") {
+            // a fence that closes on the same line is just inline code
+            if !val.contains("```") {
+                fence = Some(val.trim());
+                blocks += 1;
+                if !val.trim().is_empty() {
+                    result.push(Line { spans: vec![Span::new(val.trim(), ansi::LOW_INTENSITY)], block: Some(blocks - 1) });
+                }
+                continue;
+            }
+        }
+        let mut spans = Vec::new();
+        let mut line = line.as_str();
+        if let Some(val) = line.strip_prefix(">>> ") {
+            quote = true;
+            line = val;
+        } else if let Some(val) = line.strip_prefix("> ") {
+            spans.push(Span::new(QUOTE, ansi::LOW_INTENSITY));
+            line = val;
+        }
+        if quote {
+            spans.push(Span::new(QUOTE, ansi::LOW_INTENSITY));
+        }
+        spans.append(&mut inline(line, reveal, dict));
+        result.push(Line::new(spans));
+    }
+    result
+}
+/// Every code block in a message, as its language tag and its code.
+pub fn code_blocks(lines: &[String]) -> Vec<(String, String)> {
+    let mut result: Vec<(String, String)> = Vec::new();
+    let mut fence = false;
+    for line in lines {
+        if fence {
+            let (code, end) = match line.trim_end().strip_suffix("```") {
+                Some(val) => (val, true),
+                None => (line.as_str(), false),
+            };
+            if let Some(block) = result.last_mut() {
+                if !end || !code.is_empty() {
+                    block.1 += code;
+                    block.1.push('\n');
+                }
+            }
+            fence = !end;
+        } else if let Some(val) = line.strip_prefix("```").filter(|x| !x.contains("```")) {
+            fence = true;
+            result.push((val.trim().to_string(), String::new()));
+        }
+    }
+    result
+}
+/// The text of a message outside of code, one string per line, for finding the pings in it.
+pub fn prose(lines: &[String]) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut fence = false;
+    for line in lines {
+        if fence {
+            fence = line.trim_end().strip_suffix("```").is_none();
+            continue;
+        }
+        if line.strip_prefix("```").map(|x| !x.contains("```")).unwrap_or(false) {
+            fence = true;
+            continue;
+        }
+        let mut text = String::new();
+        let mut rest = line.as_str();
+        while let Some(c) = rest.chars().next() {
+            if c == '\\' && rest[1..].starts_with('`') {
+                text.push('`');
+                rest = &rest[2..];
+                continue;
+            }
+            if c == '`' {
+                let count = rest.chars().take_while(|x| *x == '`').count();
+                let marker = &rest[..count];
+                if let Some(end) = rest[count..].find(marker) {
+                    rest = &rest[count + end + count..];
+                    continue;
+                }
+            }
+            text.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+        result.push(text);
+    }
+    result
+}
+/// Renders the inline formatting of a single line.
+fn inline(line: &str, reveal: bool, dict: &UserDict) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut text = String::new();
+    let mut open: Vec<&str> = Vec::new();
+    let mut prev: Option<char> = None;
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        // escaped markup is taken literally
+        if c == '\\' {
+            if let Some(next) = rest[1..].chars().next().filter(|x| "*_~|`\\>".contains(*x)) {
+                text.push(next);
+                prev = Some(next);
+                rest = &rest[1 + next.len_utf8()..];
+                continue;
+            }
+        }
+        // nothing inside code is formatted
+        if c == '`' {
+            let count = rest.chars().take_while(|x| *x == '`').count();
+            let marker = &rest[..count];
+            if let Some(end) = rest[count..].find(marker) {
+                flush(&mut spans, &mut text, &open, reveal, dict);
+                let code = &rest[count..count + end];
+                spans.push(Span::new(code, style(&open, reveal) + ansi::REVERSE_VIDEO));
+                rest = &rest[count + end + count..];
+                prev = Some('`');
+                continue;
+            }
+        }
+        if let Some(marker) = MARKERS.iter().map(|x| x.0).find(|x| rest.starts_with(x)) {
+            let after = &rest[marker.len()..];
+            // `_` only counts at the edges of words, so snake_case is left alone
+            let word = marker == "_" && prev.map(|x| x.is_alphanumeric()).unwrap_or(false);
+            if open.contains(&marker) {
+                let word = marker == "_" && after.chars().next().map(|x| x.is_alphanumeric()).unwrap_or(false);
+                if !word {
+                    flush(&mut spans, &mut text, &open, reveal, dict);
+                    open.retain(|x| *x != marker);
+                    rest = after;
+                    prev = marker.chars().last();
+                    continue;
+                }
+            } else if !word && after.contains(marker) {
+                flush(&mut spans, &mut text, &open, reveal, dict);
+                open.push(marker);
+                rest = after;
+                prev = marker.chars().last();
+                continue;
+            }
+        }
+        text.push(c);
+        prev = Some(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    flush(&mut spans, &mut text, &open, reveal, dict);
+    spans
+}
+fn style(open: &[&str], reveal: bool) -> String {
+    let mut result: String = MARKERS.iter().filter(|x| open.contains(&x.0)).map(|x| x.1).collect();
+    if open.contains(&SPOILER) && !reveal {
+        result += ansi::LOW_INTENSITY;
+    }
+    result
+}
+fn flush(spans: &mut Vec<Span>, text: &mut String, open: &[&str], reveal: bool, dict: &UserDict) {
+    if text.is_empty() {
+        return;
+    }
+    let text = std::mem::take(text);
+    let style = style(open, reveal);
+    if open.contains(&SPOILER) && !reveal {
+        spans.push(Span::new(text.graphemes(true).map(|_| '▒').collect::<String>(), style));
+        return;
+    }
+    let mut pos = 0;
+    for (start, end, val) in mention::find(&text) {
+        if start > pos {
+            spans.push(Span::new(&text[pos..start], style.as_str()));
+        }
+        let (name, color) = val.display(dict);
+        spans.push(Span::new(name, style.clone() + color));
+        pos = end;
+    }
+    if pos < text.len() {
+        spans.push(Span::new(&text[pos..], style));
+    }
+}
+/// Splits a line into lines of at most `len` graphemes.
+/// Code is cut off instead, so that its indentation stays readable.
+pub fn wrap(line: &Line, len: usize) -> Vec<Line> {
+    let len = len.max(1);
+    let mut result = Vec::new();
+    let mut current: Vec<Span> = Vec::new();
+    let mut width = 0;
+    'outer: for span in &line.spans {
+        for grapheme in span.text.graphemes(true) {
+            if width == len {
+                if line.block.is_some() {
+                    if let Some(val) = current.last_mut() {
+                        let end = val.text.grapheme_indices(true).next_back().map(|x| x.0).unwrap_or(0);
+                        val.text.truncate(end);
+                    }
+                    current.push(Span::new("…", ansi::LOW_INTENSITY));
+                    break 'outer;
+                }
+                result.push(Line { spans: std::mem::take(&mut current), block: line.block });
+                width = 0;
+            }
+            match current.last_mut() {
+                Some(val) if val.style == span.style => val.text.push_str(grapheme),
+                _ => current.push(Span::new(grapheme, span.style.as_str())),
+            }
+            width += 1;
+        }
+    }
+    result.push(Line { spans: current, block: line.block });
+    result
+}
+/// The number of graphemes in a line of spans.
+pub fn width(line: &[Span]) -> usize {
+    line.iter().map(|x| x.text.graphemes(true).count()).sum()
+}
+/// The text of a line of spans, without any styling.
+pub fn plain(line: &[Span]) -> String {
+    line.iter().map(|x| x.text.as_str()).collect()
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(line: &str, reveal: bool) -> Vec<(String, String)> {
+        let lines = render(&[line.to_string()], reveal, &UserDict::new());
+        lines.into_iter().flat_map(|x| x.spans).map(|x| (x.text, x.style)).collect()
+    }
+    fn span(text: &str, style: &str) -> (String, String) {
+        (text.to_string(), style.to_string())
+    }
+
+    #[test]
+    fn nested_spans_combine_their_styles() {
+        let bold_italic = ansi::HIGH_INTENSITY.to_string() + ansi::ITALIC;
+        assert_eq!(
+            spans("**bold _both_** plain", false),
+            vec![span("bold ", ansi::HIGH_INTENSITY), span("both", &bold_italic), span(" plain", "")]
+        );
+    }
+
+    #[test]
+    fn unclosed_markers_are_taken_literally() {
+        assert_eq!(spans("**not bold", false), vec![span("**not bold", "")]);
+        assert_eq!(spans("a ~~b", false), vec![span("a ~~b", "")]);
+    }
+
+    #[test]
+    fn snake_case_isnt_italic() {
+        assert_eq!(spans("snake_case_name", false), vec![span("snake_case_name", "")]);
+        assert_eq!(spans("_italic_", false), vec![span("italic", ansi::ITALIC)]);
+    }
+
+    #[test]
+    fn nothing_inside_inline_code_is_formatted() {
+        assert_eq!(
+            spans("run `**x**` now", false),
+            vec![span("run ", ""), span("**x**", ansi::REVERSE_VIDEO), span(" now", "")]
+        );
+        assert_eq!(spans("``a ` b``", false), vec![span("a ` b", ansi::REVERSE_VIDEO)]);
+    }
+
+    #[test]
+    fn escaped_markers_are_literal() {
+        assert_eq!(spans(r"\*not italic\*", false), vec![span("*not italic*", "")]);
+    }
+
+    #[test]
+    fn spoilers_are_blanked_until_revealed() {
+        assert_eq!(spans("||secret||", false), vec![span("▒▒▒▒▒▒", ansi::LOW_INTENSITY)]);
+        assert_eq!(spans("||secret||", true), vec![span("secret", "")]);
+    }
+
+    #[test]
+    fn fenced_blocks_are_numbered() {
+        let lines: Vec<String> = ["```rust", "let x = 1;", "```", "text", "```", "two", "```"].iter().map(|x| x.to_string()).collect();
+        let blocks: Vec<Option<usize>> = render(&lines, false, &UserDict::new()).iter().map(|x| x.block).collect();
+        assert_eq!(blocks, vec![Some(0), Some(0), None, Some(1)]);
+        assert_eq!(code_blocks(&lines), vec![("rust".to_string(), "let x = 1;\n".to_string()), (String::new(), "two\n".to_string())]);
+    }
+
+    #[test]
+    fn unclosed_fences_run_to_the_end() {
+        let lines: Vec<String> = ["```", "**a**", "b"].iter().map(|x| x.to_string()).collect();
+        assert_eq!(code_blocks(&lines), vec![(String::new(), "**a**\nb\n".to_string())]);
+        assert!(render(&lines, false, &UserDict::new()).iter().all(|x| x.block == Some(0)));
+    }
+
+    #[test]
+    fn wrapping_splits_text_but_cuts_code() {
+        let line = Line::new(vec![Span::new("abcdef", "")]);
+        let wrapped: Vec<String> = wrap(&line, 4).iter().map(|x| plain(&x.spans)).collect();
+        assert_eq!(wrapped, vec!["abcd", "ef"]);
+        let code = Line { spans: vec![Span::new("abcdef", "")], block: Some(0) };
+        let cut: Vec<String> = wrap(&code, 4).iter().map(|x| plain(&x.spans)).collect();
+        assert_eq!(cut, vec!["abc…"]);
+    }
+}
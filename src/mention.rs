@@ -0,0 +1,64 @@
+use serenity::model::id::{ChannelId, RoleId, UserId};
+
+use crate::{ansi, message::UserDict};
+
+pub enum Mention {
+    User(UserId),
+    Channel(ChannelId),
+    Role(RoleId),
+    Emoji(String),
+}
+impl Mention {
+    fn parse(token: &str) -> Option<Mention> {
+        if let Some(val) = token.strip_prefix("@&") {
+            return val.parse().ok().map(|x| Mention::Role(RoleId(x)));
+        }
+        if let Some(val) = token.strip_prefix('@') {
+            return val.trim_start_matches('!').parse().ok().map(|x| Mention::User(UserId(x)));
+        }
+        if let Some(val) = token.strip_prefix('#') {
+            return val.parse().ok().map(|x| Mention::Channel(ChannelId(x)));
+        }
+        let val = token.strip_prefix("a:").or_else(|| token.strip_prefix(':'))?;
+        let (name, id) = val.split_once(':')?;
+        id.parse::<u64>().ok()?;
+        Some(Mention::Emoji(name.to_string()))
+    }
+    /// The text the mention is shown as, along with its style.
+    pub fn display(&self, dict: &UserDict) -> (String, &'static str) {
+        match self {
+            Mention::User(id) => {
+                let name = dict.contents.get(id).map(|x| x.name.as_str()).unwrap_or("unknown-user");
+                (format!("@{}", name), ansi::CYAN)
+            }
+            Mention::Channel(id) => {
+                let name = dict.channels.get(id).map(|x| x.as_str()).unwrap_or("unknown-channel");
+                (format!("#{}", name), ansi::BLUE)
+            }
+            Mention::Role(id) => {
+                let name = dict.roles.get(id).map(|x| x.as_str()).unwrap_or("unknown-role");
+                (format!("@{}", name), ansi::MAGENTA)
+            }
+            Mention::Emoji(name) => (format!(":{}:", name), ansi::YELLOW),
+        }
+    }
+}
+/// Finds every mention in the string, along with the byte range it covers.
+pub fn find(s: &str) -> Vec<(usize, usize, Mention)> {
+    let mut result = Vec::new();
+    let mut pos = 0;
+    while let Some(start) = s[pos..].find('<').map(|x| x + pos) {
+        let end = match s[start..].find('>') {
+            Some(val) => start + val + 1,
+            None => break,
+        };
+        match Mention::parse(&s[start + 1..end - 1]) {
+            Some(val) => {
+                result.push((start, end, val));
+                pos = end;
+            }
+            None => pos = start + 1,
+        }
+    }
+    result
+}
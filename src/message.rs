@@ -1,14 +1,17 @@
-use std::collections::{HashMap, LinkedList};
+use std::collections::{HashMap, HashSet, LinkedList};
 
 use chrono::{DateTime, Local};
 use crate::{block_on::block_on, colors::Color, task::{Task, process}};
-use serenity::model::{channel::{Attachment, Embed, Message}, id::{MessageId, UserId}};
+use serenity::model::{channel::{Attachment, Embed, Message, ReactionType}, id::{ChannelId, MessageId, RoleId, UserId}};
 use futures::channel::mpsc::Sender;
+use unicode_segmentation::UnicodeSegmentation;
 
 use crate::{
-    ansi::COLORS,
+    ansi::{self, COLORS},
     file::{fs_write, fs_write_2},
     format::format_time,
+    markdown::{self, Line, Span},
+    mention::{self, Mention},
 };
 
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
@@ -18,6 +21,8 @@ pub struct LoadedMessageInstance {
     pub attachments: Vec<String>,
     pub attachment_url: Vec<String>,
     pub time: DateTime<Local>,
+    #[serde(default)]
+    pub embeds: Vec<LoadedEmbed>,
 }
 impl LoadedMessageInstance {
     pub fn new(s: Vec<String>, time: DateTime<Local>) -> Self {
@@ -27,8 +32,131 @@ impl LoadedMessageInstance {
             attachment_url: Vec::new(),
             attachments: Vec::new(),
             time,
+            embeds: Vec::new(),
         }
     }
+    /// The lines of every embed, wrapped to `len`, along with the style each is drawn in.
+    pub fn embed_lines(&self, len: usize) -> Vec<(String, String)> {
+        self.embeds.iter().flat_map(|x| x.lines(len)).collect()
+    }
+    /// The names of the files that can be opened: attachments, then embed images.
+    pub fn file_names(&self) -> Vec<String> {
+        let images = self.embeds.iter().flat_map(|x| x.images.iter()).map(|x| "image: ".to_string() + x);
+        self.attachments.iter().cloned().chain(images).collect()
+    }
+    /// The local paths of the files that can be opened, in the same order as `file_names`.
+    pub fn file_paths(&self) -> Vec<&String> {
+        let images = self.embeds.iter().flat_map(|x| x.image_paths.iter());
+        self.attachment_url.iter().chain(images).collect()
+    }
+}
+/// The parts of an embed that are shown; image urls are downloaded like attachments.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Default)]
+pub struct LoadedEmbed {
+    pub color: u32,
+    pub author: Option<String>,
+    pub title: Option<String>,
+    pub url: Option<String>,
+    pub description: Option<String>,
+    pub fields: Vec<(String, String)>,
+    pub footer: Option<String>,
+    pub images: Vec<String>,      // file names
+    pub image_paths: Vec<String>, // where they're downloaded to
+}
+impl LoadedEmbed {
+    pub fn new(v: Embed, tasks: &mut Vec<Task>) -> Self {
+        let mut result = LoadedEmbed {
+            color: v.colour.0,
+            author: v.author.map(|x| x.name),
+            title: v.title,
+            url: v.url,
+            description: v.description,
+            fields: v.fields.into_iter().map(|x| (x.name, x.value)).collect(),
+            footer: v.footer.map(|x| x.text),
+            ..Default::default()
+        };
+        let image = v.image.map(|x| (x.url, x.proxy_url));
+        let thumbnail = v.thumbnail.map(|x| (x.url, x.proxy_url));
+        for (url, proxy_url) in image.into_iter().chain(thumbnail) {
+            let name = url.split(['/', '?'].as_ref()).rfind(|x| !x.is_empty()).unwrap_or("image").to_string();
+            // the proxy always has a host with an extension, which `process` relies on
+            let location = process(proxy_url.clone());
+            let (path, should_download) = fs_write(&location);
+            if should_download {
+                tasks.push(Task::DownloadUrl(proxy_url, location));
+            }
+            result.images.push(name);
+            result.image_paths.push(path);
+        }
+        result
+    }
+    /// Wraps the embed to `len` columns behind a bar in the embed's color.
+    pub fn lines(&self, len: usize) -> Vec<(String, String)> {
+        let color = if self.color == 0 {
+            ansi::LOW_INTENSITY.to_string()
+        } else {
+            let (r, g, b) = (self.color >> 16 & 0xff, self.color >> 8 & 0xff, self.color & 0xff);
+            format!("\u{001B}[38;2;{};{};{}m", r, g, b)
+        };
+        let mut text: Vec<(String, &str)> = Vec::new();
+        if let Some(val) = &self.author {
+            text.push((val.clone(), ansi::ITALIC));
+        }
+        if let Some(val) = &self.title {
+            text.push((val.clone(), ansi::HIGH_INTENSITY));
+        }
+        if let Some(val) = &self.url {
+            text.push((val.clone(), ansi::UNDERLINE));
+        }
+        for line in self.description.iter().flat_map(|x| x.split('\n')) {
+            text.push((line.to_string(), ""));
+        }
+        for (name, value) in &self.fields {
+            text.push((name.clone(), ansi::HIGH_INTENSITY));
+            for line in value.split('\n') {
+                text.push((line.to_string(), ""));
+            }
+        }
+        if let Some(val) = &self.footer {
+            text.push((val.clone(), ansi::LOW_INTENSITY));
+        }
+        let width = len.saturating_sub(EMBED_INDENT.chars().count()).max(1);
+        let mut result = Vec::new();
+        for (line, style) in text {
+            let graphemes: Vec<&str> = line.graphemes(true).collect();
+            for chunk in graphemes.chunks(width) {
+                result.push((EMBED_INDENT.to_string() + &chunk.concat(), color.clone() + style));
+            }
+            if graphemes.is_empty() {
+                result.push((EMBED_INDENT.to_string(), color.clone()));
+            }
+        }
+        result
+    }
+}
+const EMBED_INDENT: &str = "  ▌ ";
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct ReactionCount {
+    pub kind: ReactionType,
+    pub count: u64,
+    pub me: bool, // whether the logged-in user has reacted with this
+}
+/// The message that a reply was responding to.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct ReplyHeader {
+    pub username: Option<String>, // None if the original was deleted
+    pub snippet: String,
+}
+impl ReplyHeader {
+    pub fn to_string(&self, len: usize) -> String {
+        match &self.username {
+            Some(name) => format!("↳ replying to {}: {}", name, self.snippet),
+            None => "↳ replying to a deleted message".to_string(),
+        }
+        .chars()
+        .take(len)
+        .collect()
+    }
 }
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
 pub struct LoadedMessage {
@@ -39,6 +167,14 @@ pub struct LoadedMessage {
     pub first_time: DateTime<Local>,
     pub id: MessageId,
     pub username: String,
+    #[serde(default)]
+    pub deleted: bool,
+    #[serde(default)]
+    pub reply: Option<ReplyHeader>,
+    #[serde(default)]
+    pub reactions: Vec<ReactionCount>,
+    #[serde(skip)]
+    pub revealed: bool, // whether spoilers are shown
 }
 impl LoadedMessage {
     pub fn from_message(msg: Message, tasks: &mut Vec<Task>) -> Self {
@@ -47,6 +183,20 @@ impl LoadedMessage {
         for line in msg.attachments {
             v = v.attachment(line, tasks);
         }
+        for embed in msg.embeds {
+            v = v.embed(embed, tasks);
+        }
+        v.reactions = msg.reactions.into_iter().map(|x| ReactionCount {
+            kind: x.reaction_type,
+            count: x.count,
+            me: x.me,
+        }).collect();
+        if msg.message_reference.is_some() {
+            v.reply = Some(ReplyHeader {
+                username: msg.referenced_message.as_ref().map(|x| x.author.name.clone()),
+                snippet: msg.referenced_message.map(|x| x.content.lines().next().unwrap_or("").to_string()).unwrap_or_default(),
+            });
+        }
         v
     }
     pub fn from_content(
@@ -64,14 +214,120 @@ impl LoadedMessage {
             first_time: time,
             id,
             username,
+            deleted: false,
+            reply: None,
+            reactions: Vec::new(),
+            revealed: false,
         }
     }
     pub fn push_content(&mut self, s: Vec<String>, time: DateTime<Local>) {
-        self.next.push_front(LoadedMessageInstance::new(s, time));
+        let following = self.next.is_empty();
+        let mut instance = LoadedMessageInstance::new(s, time);
+        // edits can't change attachments, so they're carried over
+        instance.attachments = self.last().attachments.clone();
+        instance.attachment_url = self.last().attachment_url.clone();
+        instance.embeds = self.last().embeds.clone();
+        self.next.push_front(instance);
+        if following {
+            self.newer();
+        }
     } // used for messages with edit history
     pub fn last(&mut self) -> &mut LoadedMessageInstance {
         self.next.front_mut().unwrap_or(&mut self.content)
     }
+    /// The newest revision, whichever one is shown.
+    pub fn latest(&self) -> &LoadedMessageInstance {
+        self.next.front().unwrap_or(&self.content)
+    }
+    /// Steps back to the previous revision. Returns false if this is the original.
+    pub fn older(&mut self) -> bool {
+        if let Some(val) = self.prev.pop_back() {
+            let current = std::mem::replace(&mut self.content, val);
+            self.next.push_back(current);
+            true
+        } else {
+            false
+        }
+    }
+    /// Steps forward to the next revision. Returns false if this is the latest.
+    pub fn newer(&mut self) -> bool {
+        if let Some(val) = self.next.pop_back() {
+            let current = std::mem::replace(&mut self.content, val);
+            self.prev.push_back(current);
+            true
+        } else {
+            false
+        }
+    }
+    pub fn cycle_revision(&mut self) {
+        if !self.newer() {
+            while self.older() {}
+        }
+    }
+    /// Counts a reaction being added or removed.
+    pub fn react(&mut self, kind: ReactionType, added: bool, me: bool) {
+        match self.reactions.iter().position(|x| x.kind == kind) {
+            Some(pos) => {
+                let val = &mut self.reactions[pos];
+                if added {
+                    val.count += 1;
+                    val.me |= me;
+                } else {
+                    val.count = val.count.saturating_sub(1);
+                    val.me &= !me;
+                    if val.count == 0 {
+                        self.reactions.remove(pos);
+                    }
+                }
+            }
+            None if added => self.reactions.push(ReactionCount { kind, count: 1, me }),
+            None => {}
+        }
+    }
+    pub fn reaction_line(&self, len: usize) -> String {
+        self.reactions
+            .iter()
+            .map(|x| {
+                let val = format!("{} {}", crate::emoji::display(&x.kind), x.count);
+                if x.me {format!("[{}]", val)} else {val}
+            })
+            .collect::<Vec<_>>()
+            .join("  ")
+            .chars()
+            .take(len)
+            .collect()
+    }
+    /// Every line shown below the username, wrapped to `len`.
+    pub fn body(&self, len: usize, dict: &UserDict) -> Vec<Line> {
+        let mut lines = markdown::render(&self.content.content, self.revealed, dict);
+        lines.extend(self.content.embed_lines(len).into_iter().map(|(x, y)| Line::new(vec![Span::new(x, y)])));
+        lines.extend(self.content.file_names().into_iter().map(|x| Line::new(vec![Span::new(x, "")])));
+        if !self.reactions.is_empty() {
+            lines.push(Line::new(vec![Span::new(self.reaction_line(len), "")]));
+        }
+        lines.iter().flat_map(|x| markdown::wrap(x, len)).collect()
+    }
+    /// Every mention in the shown revision.
+    pub fn mentions(&self) -> impl Iterator<Item = Mention> + '_ {
+        self.content.content.iter().flat_map(|x| mention::find(x)).map(|x| x.2)
+    }
+    /// Whether the shown revision pings the given user. Anything written in code doesn't count.
+    pub fn mentions_user(&self, user: UserId) -> bool {
+        markdown::prose(&self.content.content).iter().any(|x| {
+            x.contains("@everyone")
+                || x.contains("@here")
+                || mention::find(x).into_iter().any(|y| matches!(y.2, Mention::User(id) if id == user))
+        })
+    }
+    /// Whether the shown revision contains the (lowercase) query in its text, author or attachment names.
+    pub fn matches(&self, query: &str) -> bool {
+        self.username.to_lowercase().contains(query)
+            || self.content.content.iter().any(|x| x.to_lowercase().contains(query))
+            || self.content.attachments.iter().any(|x| x.to_lowercase().contains(query))
+    }
+    pub fn revisions(&self) -> usize {
+        self.prev.len() + self.next.len() + 1
+    }
     pub fn attachment(mut self, v: Attachment, tasks: &mut Vec<Task>) -> Self {
         let url = process(v.url.clone());
         let name = v.filename.clone();
@@ -83,18 +339,10 @@ impl LoadedMessage {
         self.last().attachments.push(name);
         self
     }
-    pub fn embed(mut self, v: Embed) -> Self {
+    pub fn embed(mut self, v: Embed, tasks: &mut Vec<Task>) -> Self {
+        self.last().embeds.push(LoadedEmbed::new(v, tasks));
         self
     }
-    /*pub fn embed(mut self, v: Embed) -> Self {
-        let url = v.url.clone();
-        let img = v.image.unwrap().url;
-        let name = v.filename.clone();
-        let location = fs_write(|| block_on(v.download()).ok(), url).unwrap_or("/".to_string());
-        self.last().attachment_url.push(location);
-        self.last().attachments.push(name);
-        self
-    }*/
     pub fn user(&self, dict: &UserDict, len: usize) -> String {
         let info = &dict.contents[&self.user];
         if len < info.name.len() {
@@ -102,12 +350,23 @@ impl LoadedMessage {
                 + &*info.name.chars().take(len).collect::<String>();
         } else {
             return  info.color.to_ansi_value()
-                + &*(info.name.clone() + " " + &*format_time(self.content.time))
+                + &*(info.name.clone() + " " + &*format_time(self.content.time) + &self.revision_marker())
                     .chars()
                     .take(len)
                     .collect::<String>();
         }
     }
+    fn revision_marker(&self) -> String {
+        if self.deleted {
+            " (deleted)".to_string()
+        } else if self.revisions() == 1 {
+            String::new()
+        } else if self.next.is_empty() {
+            " (edited)".to_string()
+        } else {
+            format!(" (revision {}/{})", self.prev.len() + 1, self.revisions())
+        }
+    }
     pub fn red(&self, dict: &mut UserDict) {
         let mut info = (&dict.contents[&self.user]).clone();
         info.color.red();
@@ -129,14 +388,27 @@ pub struct UserInfo {
     pub name: String,
     pub color: Color,
 }
+/// Everything needed to show names instead of ids: users, channels and roles.
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
 pub struct UserDict {
     pub contents: HashMap<UserId, UserInfo>,
+    #[serde(default)]
+    pub channels: HashMap<ChannelId, String>,
+    #[serde(default)]
+    pub roles: HashMap<RoleId, String>,
+    #[serde(default)]
+    pub me: UserId,
+    #[serde(skip)]
+    pub pending: HashSet<UserId>, // users being looked up, so they aren't asked for twice
 }
 impl UserDict {
     pub fn new() -> Self {
         Self {
             contents: HashMap::new(),
+            channels: HashMap::new(),
+            roles: HashMap::new(),
+            me: UserId(0),
+            pending: HashSet::new(),
         }
     }
 }
\ No newline at end of file
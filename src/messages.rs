@@ -1,18 +1,20 @@
-use std::{collections::VecDeque, io::Stdout, sync::{Arc, mpsc::Sender}, time::Duration};
+use std::{collections::{HashMap, VecDeque}, io::Stdout, sync::{Arc, mpsc::Sender}, time::Duration};
 
-use chrono::Local;
+use chrono::{DateTime, Local};
 use crossterm::{cursor::MoveTo, queue, style::Print};
 
-use crate::{block_on::block_on, colors::Color, input::Response, task::{Task}};
-use serenity::{Client, model::{channel::{Channel, Message, PrivateChannel}, id::MessageId}};
+use crate::{block_on::block_on, colors::Color, input::{REQUEST_LEN, Response}, task::{Task, Until}};
+use serenity::{Client, model::{channel::{Channel, Message, PrivateChannel, Reaction}, event::MessageUpdateEvent, id::{ChannelId, GuildId, MessageId, UserId}}};
 use unicode_segmentation::UnicodeSegmentation;
 
 use crate::{
     ansi,
     file::ExtConfig,
-    render::Grid,
+    render::{self, Grid},
     input::Parser,
-    message::{LoadedMessage, UserDict, UserInfo},
+    markdown::{self, Span},
+    mention::Mention,
+    message::{LoadedEmbed, LoadedMessage, UserDict, UserInfo},
 };
 
 pub enum Messages {
@@ -33,6 +35,14 @@ impl Messages {
             val.flag();
         }
     }
+    /// Replaces the channel's details, such as its name and permissions, after it's been updated.
+    pub fn set_id(&mut self, ch: Channel) {
+        match self {
+            Messages::Unloaded(val) | Messages::Loading(val) => *val = ch,
+            Messages::Loaded(val) => val.id = ch,
+            Messages::Nonexistent => {}
+        }
+    }
     pub fn id(&self) -> Option<Channel> {
         match &self {
             Messages::Unloaded(val) => Some(val.clone()),
@@ -88,21 +98,67 @@ impl Messages {
             panic!("unwrap failed!")
         }
     }
-    pub fn receive_message(&mut self, dict: &mut UserDict, tasks: &Sender<Task>, msg: Message) {
+    /// Returns true once the message has been accounted for, and false if it was kicked down the road.
+    pub fn receive_message(&mut self, dict: &mut UserDict, tasks: &Sender<Task>, msg: Message) -> bool {
         match self {
-            Messages::Unloaded(ch) => {
-                tasks.send(Task::GetNewMessages(ch.clone()));
-                tasks.send(Task::Kick(Response::Message(msg), Duration::from_millis(100)));
-            },
+            // the message will be fetched along with the rest of the channel once it's opened
+            Messages::Unloaded(_) => true,
             Messages::Loading(_) => {
-                tasks.send(Task::Kick(Response::Message(msg), Duration::from_millis(100)));
+                tasks.send(Task::Kick(Response::Message(msg), Duration::from_millis(100))).expect("could not send!");
+                false
             },
             Messages::Loaded(val) => {
-                val.receive_message(dict, tasks, msg);
+                val.receive_message(dict, tasks, msg)
             },
             Messages::Nonexistent => todo!(),
         }
-    } 
+    }
+    pub fn receive_edit(&mut self, tasks: &Sender<Task>, event: MessageUpdateEvent) {
+        match self {
+            // the edit will already be included once the channel loads
+            Messages::Unloaded(_) | Messages::Nonexistent => {},
+            Messages::Loading(_) => {
+                tasks.send(Task::Kick(Response::Edit(event), Duration::from_millis(100))).expect("could not send!");
+            },
+            Messages::Loaded(val) => {
+                for task in val.receive_edit(event) {
+                    tasks.send(task).expect("could not send!");
+                }
+            },
+        }
+    }
+    pub fn receive_reaction(&mut self, tasks: &Sender<Task>, reaction: Reaction, added: bool, me: UserId) {
+        match self {
+            // reactions are included when the channel is loaded
+            Messages::Unloaded(_) | Messages::Nonexistent => {},
+            Messages::Loading(_) => {
+                tasks.send(Task::Kick(Response::Reaction(reaction, added), Duration::from_millis(100))).expect("could not send!");
+            },
+            Messages::Loaded(val) => {
+                if let Some(msg) = val.labels.iter_mut().find(|x| x.id == reaction.message_id) {
+                    msg.react(reaction.emoji, added, reaction.user_id == Some(me));
+                    val.flag = true;
+                }
+            },
+        }
+    }
+    pub fn receive_delete(&mut self, tasks: &Sender<Task>, ch: ChannelId, messages: Vec<MessageId>, guild: Option<GuildId>) {
+        match self {
+            // there's nothing stored locally to keep a record of
+            Messages::Unloaded(_) | Messages::Nonexistent => {},
+            Messages::Loading(_) => {
+                tasks.send(Task::Kick(Response::Delete(ch, messages, guild), Duration::from_millis(100))).expect("could not send!");
+            },
+            Messages::Loaded(val) => {
+                val.receive_delete(messages);
+            },
+        }
+    }
+}
+pub enum SearchResult {
+    Found,
+    Searching,
+    Missing,
 }
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub enum LoadingState {
@@ -120,6 +176,16 @@ pub struct LoadedMessages {
     pub before: LoadingState,
     pub after: LoadingState,
     pub flag: bool,
+    pub hide_deleted: bool,
+    pub search: Option<String>, // lowercase
+    pub searching: bool, // whether older messages are being loaded to find a match
+    pub unindexed: Vec<MessageId>, // messages added or edited since the search index was last updated
+    pub gaps: HashMap<MessageId, LoadingState>, // messages that may be followed by ones that aren't loaded
+    pub target: Option<DateTime<Local>>, // the date being jumped to while older messages load
+    pub bookmarks: Vec<(String, MessageId)>,
+    pub rows: Vec<Option<(MessageId, usize)>>, // the message and line on each row when last drawn
+    pub refresh: Option<MessageId>, // saved messages after this one are fetched again once the channel is drawn
+    pub present: bool, // whether the cursor follows the newest messages while they load
 }
 impl LoadedMessages {
     pub fn with_messages(
@@ -163,6 +229,16 @@ impl LoadedMessages {
             id,
             before: LoadingState::Finished,
             after: LoadingState::Finished,
+            hide_deleted: false,
+            search: None,
+            searching: false,
+            unindexed: Vec::new(),
+            gaps: HashMap::new(),
+            target: None,
+            bookmarks: Vec::new(),
+            rows: Vec::new(),
+            refresh: None,
+            present: false,
         }
     }
     pub fn flag(&mut self) {
@@ -183,26 +259,27 @@ impl LoadedMessages {
         self.flag = true;
         v.green(dict);
     }
-    pub fn up(&mut self, grid: &Grid) {
-        let cap_len = self.count(grid, self.current) - 1;
+    pub fn up(&mut self, grid: &Grid, dict: &UserDict) {
+        self.present = false;
+        let cap_len = self.count(grid, self.current, dict) - 1;
         while self.current_in_message > cap_len {
             self.current_in_message -= 1;
         }
         if self.current_in_message > 0 {
             self.current_in_message -= 1;
             self.flag = true;
-        } else if self.current > 0 {
-            self.current -= 1;
-            self.current_in_message = self.count(grid, self.current) - 1;
+        } else if let Some(prev) = (0..self.current).rev().find(|x| self.visible(*x)) {
+            self.current = prev;
+            self.current_in_message = self.count(grid, self.current, dict) - 1;
             self.flag = true;
         }
     }
-    pub fn down(&mut self, grid: &Grid) {
-        if self.current_in_message < self.count(grid, self.current) - 1 {
+    pub fn down(&mut self, grid: &Grid, dict: &UserDict) {
+        if self.current_in_message < self.count(grid, self.current, dict) - 1 {
             self.current_in_message += 1;
             self.flag = true;
-        } else if self.current < self.labels.len() - 1 {
-            self.current += 1;
+        } else if let Some(next) = (self.current + 1..self.labels.len()).find(|x| self.visible(*x)) {
+            self.current = next;
             self.current_in_message = 0;
             self.flag = true;
         }
@@ -220,12 +297,44 @@ impl LoadedMessages {
         }
     }
     pub fn ctrl_up(&mut self) {
-        self.current = 0;
-        self.current_in_message = 0;
+        self.present = false;
+        if let Some(first) = (0..self.labels.len()).find(|x| self.visible(*x)) {
+            self.current = first;
+            self.current_in_message = 0;
+            self.flag = true;
+        }
+    }
+    pub fn ctrl_down(&mut self, grid: &Grid, dict: &UserDict) {
+        if let Some(last) = (0..self.labels.len()).rev().find(|x| self.visible(*x)) {
+            self.current = last;
+            self.current_in_message = self.count(grid, last, dict) - 1;
+            self.flag = true;
+        }
     }
-    pub fn ctrl_down(&mut self, grid: &Grid) {
-        self.current = self.labels.len();
-        self.current_in_message = self.count(grid, self.labels.len() - 1);
+    /// Moves to the newest message, loading any that are missing after it.
+    pub fn jump_to_present(&mut self, grid: &Grid, dict: &UserDict, tasks: &Sender<Task>) {
+        self.ctrl_down(grid, dict);
+        if let Some(last) = self.labels.len().checked_sub(1) {
+            self.fill_gap(last, tasks);
+        }
+        self.present = !matches!(self.after, LoadingState::Finished);
+    }
+    /// The name of the bookmark on the current message, if it has one.
+    pub fn bookmark(&self) -> Option<&str> {
+        let id = self.labels.get(self.current)?.id;
+        self.bookmarks.iter().find(|x| x.1 == id).map(|x| x.0.as_str())
+    }
+    /// Bookmarks the current message, or removes its bookmark if `name` is None.
+    pub fn set_bookmark(&mut self, name: Option<String>) {
+        let id = match self.labels.get(self.current) {
+            Some(val) => val.id,
+            None => return,
+        };
+        self.bookmarks.retain(|x| x.1 != id);
+        if let Some(name) = name {
+            self.bookmarks.push((name, id));
+        }
+        self.flag = true;
     }
     pub fn select(&mut self) {
         self.selected = self.current;
@@ -239,22 +348,202 @@ impl LoadedMessages {
         self.unread = pos;
         self.flag = true;
     }
-    pub fn receive_message(&mut self, dict: &mut UserDict, tasks: &Sender<Task>, msg: Message) {
-        match self.after {
-            LoadingState::Finished => {
-                let mut task_buffer = Vec::new();
-                self.add_complete(crate::message::LoadedMessage::from_message(msg, &mut task_buffer), None, dict);
-                for line in task_buffer {
-                    tasks.send(line);
-                }
-            },
-            LoadingState::Unloaded => {
-                tasks.send(Task::GetMessagesAfter(self.id.clone(), self.labels.front().map(|x| x.id).unwrap_or(MessageId(0)))).expect("could not send!");
-                tasks.send(Task::Kick(Response::Message(msg), Duration::from_millis(100))).expect("could not send!");
-            },
-            LoadingState::Loading => {
-                tasks.send(Task::Kick(Response::Message(msg), Duration::from_millis(100))).expect("could not send!");
-            },
+    pub fn receive_message(&mut self, dict: &mut UserDict, tasks: &Sender<Task>, msg: Message) -> bool {
+        // messages are live from here on, so anything missed before this one is left as a gap
+        if !matches!(self.after, LoadingState::Finished) {
+            let state = std::mem::replace(&mut self.after, LoadingState::Finished);
+            if let Some(last) = self.labels.back() {
+                self.gaps.insert(last.id, state);
+            }
+        }
+        let mut task_buffer = Vec::new();
+        self.insert(crate::message::LoadedMessage::from_message(msg, &mut task_buffer), dict);
+        for line in task_buffer {
+            tasks.send(line).expect("could not send!");
+        }
+        true
+    }
+    /// Marks everything after the newest message as possibly missing, e.g. after reconnecting.
+    pub fn stale(&mut self) {
+        if matches!(self.after, LoadingState::Finished) {
+            self.after = LoadingState::Unloaded;
+        }
+    }
+    /// Adds a message in order, or compares it to the stored copy if it's already loaded.
+    fn insert(&mut self, msg: LoadedMessage, dict: &mut UserDict) {
+        match self.labels.binary_search_by_key(&msg.id, |x| x.id) {
+            Ok(pos) => {
+                // differences are kept as an edit, since the original history can't be fetched
+                let content = msg.content.content.join("\n");
+                self.revise(msg.id, &content, Local::now());
+                self.labels[pos].reactions = msg.reactions;
+            }
+            Err(pos) if pos == self.labels.len() => self.add_complete(msg, None, dict),
+            Err(pos) => self.add_complete(msg, Some(pos), dict),
+        }
+    }
+    /// Whether messages might be missing right after the one at the given position.
+    fn gap_after(&self, pos: usize) -> bool {
+        if pos + 1 == self.labels.len() {
+            !matches!(self.after, LoadingState::Finished)
+        } else {
+            self.gaps.contains_key(&self.labels[pos].id)
+        }
+    }
+    /// Starts loading the messages missing after the one at the given position.
+    fn fill_gap(&mut self, pos: usize, tasks: &Sender<Task>) {
+        let id = self.labels[pos].id;
+        let state = if pos + 1 == self.labels.len() {
+            &mut self.after
+        } else {
+            match self.gaps.get_mut(&id) {
+                Some(val) => val,
+                None => return,
+            }
+        };
+        if matches!(state, LoadingState::Unloaded) {
+            *state = LoadingState::Loading;
+            tasks.send(Task::GetMessagesAfter(self.id.clone(), id)).expect("Could not send!");
+        }
+    }
+    /// Whether there's nothing left to scroll down to.
+    pub fn at_bottom(&self) -> bool {
+        !(self.current + 1..self.labels.len()).any(|x| self.visible(x))
+    }
+    /// Applies an edit, returning any downloads needed for new embed images.
+    pub fn receive_edit(&mut self, event: MessageUpdateEvent) -> Vec<Task> {
+        // edits without content (e.g. embeds resolving) aren't revisions
+        let id = event.id;
+        if let Some(content) = event.content {
+            let time = event.edited_timestamp.map(|x| x.with_timezone(&Local)).unwrap_or_else(Local::now);
+            self.revise(id, &content, time);
+        }
+        let mut tasks = Vec::new();
+        if let Some(embeds) = event.embeds {
+            if let Some(msg) = self.labels.iter_mut().find(|x| x.id == id) {
+                msg.last().embeds = embeds.into_iter().map(|x| LoadedEmbed::new(x, &mut tasks)).collect();
+                self.flag = true;
+            }
+        }
+        tasks
+    }
+    /// Adds a new revision to a message, unless it's already the latest one.
+    pub fn revise(&mut self, id: MessageId, content: &str, time: DateTime<Local>) {
+        let content: Vec<String> = content.split('\n').map(|x| x.to_string()).collect();
+        if let Some(msg) = self.labels.iter_mut().find(|x| x.id == id) {
+            // local edits are echoed back by the gateway
+            if msg.last().content != content {
+                msg.push_content(content, time);
+                self.unindexed.push(id);
+                self.flag = true;
+            }
+        }
+    }
+    pub fn receive_delete(&mut self, messages: Vec<MessageId>) {
+        for msg in self.labels.iter_mut().filter(|x| messages.contains(&x.id)) {
+            msg.deleted = true;
+            self.unindexed.push(msg.id);
+            self.flag = true;
+        }
+        self.settle();
+    }
+    /// Whether a message is shown, i.e. it isn't a hidden tombstone.
+    fn visible(&self, pos: usize) -> bool {
+        !(self.hide_deleted && self.labels[pos].deleted)
+    }
+    /// Moves the cursor off of a hidden message, preferring newer messages.
+    fn settle(&mut self) {
+        if self.current < self.labels.len() && !self.visible(self.current) {
+            let next = (self.current + 1..self.labels.len())
+                .find(|x| self.visible(*x))
+                .or_else(|| (0..self.current).rev().find(|x| self.visible(*x)));
+            if let Some(val) = next {
+                self.current = val;
+                self.current_in_message = 0;
+            }
+        }
+    }
+    pub fn toggle_deleted(&mut self) {
+        self.hide_deleted = !self.hide_deleted;
+        self.settle();
+        self.flag = true;
+    }
+    /// Moves to the given message if it's loaded.
+    pub fn jump_to(&mut self, id: MessageId) -> bool {
+        if let Some(pos) = self.labels.iter().position(|x| x.id == id) {
+            if !self.visible(pos) {
+                self.hide_deleted = false;
+            }
+            self.current = pos;
+            self.current_in_message = 0;
+            self.flag = true;
+            true
+        } else {
+            false
+        }
+    }
+    pub fn start_search(&mut self, query: &str) {
+        self.search = if query.is_empty() {None} else {Some(query.to_lowercase())};
+        self.searching = false;
+        self.flag = true;
+    }
+    fn is_match(&self, pos: usize) -> bool {
+        match &self.search {
+            Some(query) => self.visible(pos) && self.labels[pos].matches(query),
+            None => false,
+        }
+    }
+    /// Moves to the closest older match. If there isn't one loaded and `page` is set,
+    /// older messages are loaded until one is found or the channel runs out.
+    pub fn search_older(&mut self, tasks: &Sender<Task>, page: bool) -> SearchResult {
+        if let Some(pos) = (0..self.current).rev().find(|x| self.is_match(*x)) {
+            self.current = pos;
+            self.current_in_message = 0;
+            self.searching = false;
+            self.flag = true;
+            return SearchResult::Found;
+        }
+        match self.before {
+            LoadingState::Unloaded | LoadingState::Loading if page && self.search.is_some() => {
+                self.searching = true;
+                self.update(tasks);
+                SearchResult::Searching
+            }
+            _ => {
+                self.searching = false;
+                SearchResult::Missing
+            }
+        }
+    }
+    /// Moves to the closest newer match.
+    pub fn search_newer(&mut self) -> SearchResult {
+        if let Some(pos) = (self.current + 1..self.labels.len()).find(|x| self.is_match(*x)) {
+            self.current = pos;
+            self.current_in_message = 0;
+            self.flag = true;
+            SearchResult::Found
+        } else {
+            SearchResult::Missing
+        }
+    }
+    /// Called whenever older messages arrive, to keep a paging search going.
+    pub fn continue_search(&mut self, tasks: &Sender<Task>) {
+        if self.searching {
+            self.search_older(tasks, true);
+        }
+    }
+    /// Reveals or hides the spoilers in the current message.
+    pub fn toggle_spoilers(&mut self) {
+        if let Some(msg) = self.labels.get_mut(self.current) {
+            msg.revealed = !msg.revealed;
+            self.flag = true;
+        }
+    }
+    /// Shows the next revision of the current message, wrapping around to the original.
+    pub fn cycle_revision(&mut self) {
+        if let Some(msg) = self.labels.get_mut(self.current) {
+            msg.cycle_revision();
+            self.flag = true;
         }
     }
     pub fn add_complete(&mut self, msg: LoadedMessage, pos: Option<usize>, dict: &mut UserDict) {
@@ -266,6 +555,7 @@ impl LoadedMessages {
             });
         let content = msg.content.clone();
         let name = msg.username.clone();
+        self.unindexed.push(msg.id);
         if let Some(pos) = pos {
             if pos <= self.current {
                 self.current += 1;
@@ -273,6 +563,9 @@ impl LoadedMessages {
             if pos <= self.selected {
                 self.selected += 1;
             }
+            if self.unread > 0 && pos <= self.unread {
+                self.unread += 1;
+            }
             self.labels.insert(pos, msg);
         } else {
             self.labels.push_back(msg);
@@ -356,76 +649,129 @@ impl LoadedMessages {
         }
     }
     fn draw_real(&mut self, grid: &Grid, out: &mut Stdout, dict: &mut UserDict, tasks: &Sender<Task>) {
+        if let Some(anchor) = self.refresh.take() {
+            // edits and deletions are found by comparing the page to what was saved
+            tasks.send(Task::GetMessagesAfter(self.id.clone(), anchor)).expect("Could not send!");
+        }
         let mut counter = 0;
-        let start = self.beginning_pos(grid.height());
-        let sample = " ".graphemes(true).cycle();
+        let start = render::scroll_start(self.current, self.labels.len(), grid.height());
         let mut hover_pos = usize::MAX;
         let mut selected_pos: usize = usize::MAX; // will never be encountered if not assigned to
-        let mut result: Vec<String> = Vec::new(); // contains all the right strings
-        for i in start..start + grid.height().min(self.labels.len()) {
-            let mut indicator = 0;
-            let temp = &self.labels[i].user(dict, grid.len_messages());
-            let val = Some(temp).into_iter();
-            let temp = self.labels[i].content.attachments.iter();
-            for mut j in val.chain(self.labels[i].content.content.iter()).chain(temp) {
-                let temp = " ".to_string();
-                if j.is_empty() {
-                    j = &temp;
+        let mut result: Vec<Vec<Span>> = Vec::new(); // contains all the right strings
+        let mut styles: Vec<String> = Vec::new(); // extra formatting for each of the strings
+        let mut owners: Vec<Option<(MessageId, usize)>> = Vec::new(); // the message and line of each of the strings
+        let mut gaps: Vec<usize> = Vec::new(); // shown gaps, which are loaded once drawn
+        for i in (start..self.labels.len()).filter(|x| self.visible(*x)).take(grid.height()) {
+            if i == self.unread && i > 0 {
+                counter += 1;
+                owners.push(None);
+                styles.push(ansi::RED.to_string());
+                result.push(vec![Span::new(
+                    "-- new messages "
+                        .graphemes(true)
+                        .chain("-".graphemes(true).cycle())
+                        .take(grid.len_messages())
+                        .collect::<String>(),
+                    "",
+                )]);
+            }
+            self.fetch_users(i, dict, tasks);
+            let style = if self.labels[i].deleted {
+                ansi::LOW_INTENSITY.to_string() + ansi::CROSSED_OUT
+            } else if self.labels[i].mentions_user(dict.me) {
+                ansi::BACKGROUND_MENTION.to_string()
+            } else {
+                String::new()
+            };
+            let search = self.search.as_ref().filter(|_| self.is_match(i));
+            let msg = &self.labels[i];
+            let mut lines: Vec<(Vec<Span>, String)> = Vec::new(); // each line along with any extra style
+            if let Some(val) = &msg.reply {
+                lines.push((vec![Span::new(val.to_string(grid.len_messages()), "")], ansi::LOW_INTENSITY.to_string() + ansi::ITALIC));
+            }
+            match self.bookmarks.iter().find(|x| x.1 == msg.id) {
+                Some((name, _)) => {
+                    let mark = format!("[{}] ", name);
+                    let user = msg.user(dict, grid.len_messages().saturating_sub(mark.graphemes(true).count()));
+                    lines.push((vec![Span::new(mark, ansi::YELLOW), Span::new(user, "")], String::new()));
+                }
+                None => lines.push((vec![Span::new(msg.user(dict, grid.len_messages()), "")], String::new())),
+            }
+            lines.extend(msg.body(grid.len_messages(), dict).into_iter().map(|x| {
+                let extra = if x.block.is_some() {ansi::BACKGROUND_CODE} else {""};
+                (x.spans, extra.to_string())
+            }));
+            for (indicator, (mut line, extra)) in lines.into_iter().enumerate() {
+                if i == self.current && indicator == self.current_in_message {
+                    hover_pos = counter;
+                }
+                if i == self.selected && indicator == 0 {
+                    selected_pos = counter;
                 }
-                for line in j
-                    .graphemes(true)
-                    .collect::<Vec<_>>()
-                    .chunks(grid.len_messages())
-                    .map(|x| x.iter().fold(String::new(), |x, y| x + *y))
-                {
-                    if i == self.current && indicator == self.current_in_message {
-                        hover_pos = counter;
-                    }
-                    if i == self.selected && indicator == 0 {
-                        selected_pos = counter;
-                    }
-                    counter += 1;
-                    indicator += 1;
-                    result.push(
-                        line.graphemes(true)
-                            .chain(sample.clone())
-                            .take(grid.len_messages())
-                            .collect(),
-                    );
+                counter += 1;
+                owners.push(Some((msg.id, indicator)));
+                if search.map(|x| markdown::plain(&line).to_lowercase().contains(x)).unwrap_or(false) {
+                    styles.push(style.clone() + ansi::YELLOW + ansi::UNDERLINE);
+                } else {
+                    styles.push(style.clone() + &extra);
                 }
+                let padding = grid.len_messages().saturating_sub(markdown::width(&line));
+                line.push(Span::new(" ".repeat(padding), ""));
+                result.push(line);
             }
             if i == self.current && hover_pos == usize::MAX {
                 hover_pos = counter - 1;
             }
+            if self.gap_after(i) {
+                counter += 1;
+                owners.push(None);
+                styles.push(ansi::LOW_INTENSITY.to_string());
+                result.push(vec![Span::new(
+                    "-- loading missing messages "
+                        .graphemes(true)
+                        .chain("-".graphemes(true).cycle())
+                        .take(grid.len_messages())
+                        .collect::<String>(),
+                    "",
+                )]);
+                gaps.push(i);
+            }
+        }
+        for pos in gaps {
+            self.fill_gap(pos, tasks);
+        }
+        if hover_pos == usize::MAX {
+            // every message is hidden
+            hover_pos = 0;
         }
-        let start = self.beginning_minmax(grid.height(), hover_pos, result.len());
+        let start = render::scroll_start(hover_pos, result.len(), grid.height());
+        self.rows = owners.into_iter().skip(start).take(grid.height()).collect();
+        let blank = vec![Span::new(" ".repeat(grid.len_messages()), "")];
+        // rendering a message isn't cheap, so the selected one is only measured once
+        let selected_len = if selected_pos == usize::MAX {0} else {self.count(grid, self.selected, dict)};
         for i in start..start + grid.height() {
-            let val: String = result
-                .get(i)
-                .unwrap_or(&String::new())
-                .graphemes(true)
-                .chain(sample.clone())
-                .take(grid.len_messages())
-                .collect();
             let true_pos = i - start + grid.start_y;
             let _ = queue!(
                 out,
                 crossterm::cursor::MoveTo(grid.border_3 as u16, true_pos as u16,)
             );
+            let mut base = String::new(); // the style underneath every span of the line
+            if let Some(style) = styles.get(i) {
+                base += style;
+            }
             if i == hover_pos {
-                let _ = queue!(
-                    out,
-                    Print(if grid.messages_selected() {
-                        ansi::BACKGROUND_LIGHT_GREY.to_string()
-                    } else {
-                        ansi::BACKGROUND_GREY.to_string()
-                    })
-                );
-            }
-            if i >= selected_pos && i < selected_pos + self.count(grid, self.selected) {
-                let _ = queue!(out, Print(ansi::HIGH_INTENSITY.to_string()));
-            }
-            let _ = queue!(out, Print(val));
+                base += if grid.messages_selected() {
+                    ansi::BACKGROUND_LIGHT_GREY
+                } else {
+                    ansi::BACKGROUND_GREY
+                };
+            }
+            if i >= selected_pos && i < selected_pos + selected_len {
+                base += ansi::HIGH_INTENSITY;
+            }
+            for span in result.get(i).unwrap_or(&blank) {
+                let _ = queue!(out, Print(ansi::RESET.to_string() + &base + &span.style + &span.text));
+            }
             let _ = queue!(
                 out,
                 crossterm::cursor::MoveTo(grid.border_3 as u16, true_pos as u16,)
@@ -436,6 +782,88 @@ impl LoadedMessages {
             self.update(tasks);
         }
     }
+    /// Looks up the names of any mentioned users that haven't been seen before.
+    fn fetch_users(&self, pos: usize, dict: &mut UserDict, tasks: &Sender<Task>) {
+        for mention in self.labels[pos].mentions() {
+            if let Mention::User(id) = mention {
+                if !dict.contents.contains_key(&id) && dict.pending.insert(id) {
+                    tasks.send(Task::GetUser(id)).expect("Could not send!");
+                }
+            }
+        }
+    }
+    /// The code block under the cursor, or else the first one in the current message.
+    /// Returns its position in the message, its language tag and its code.
+    pub fn code_block(&self, grid: &Grid, dict: &UserDict) -> Option<(usize, String, String)> {
+        let msg = self.labels.get(self.current)?;
+        let header = if msg.reply.is_some() {2} else {1};
+        let pos = self
+            .current_in_message
+            .checked_sub(header)
+            .and_then(|x| msg.body(grid.len_messages(), dict).get(x).and_then(|y| y.block))
+            .unwrap_or(0);
+        let (lang, code) = markdown::code_blocks(&msg.content.content).into_iter().nth(pos)?;
+        Some((pos, lang, code))
+    }
+    /// The first channel mentioned in the current message.
+    pub fn mentioned_channel(&self) -> Option<ChannelId> {
+        self.labels.get(self.current)?.mentions().find_map(|x| match x {
+            Mention::Channel(id) => Some(id),
+            _ => None,
+        })
+    }
+    /// Loads older messages, either a number of pages or everything back to a date.
+    /// Returns false if there's nothing older to load.
+    pub fn load_history(&mut self, tasks: &Sender<Task>, until: Until) -> bool {
+        let first = match (&self.before, self.labels.front()) {
+            (LoadingState::Finished, _) | (_, None) => return false,
+            (_, Some(val)) => val.id,
+        };
+        tasks.send(Task::GetHistory(self.id.clone(), first, until)).expect("Could not send!");
+        self.before = LoadingState::Loading;
+        true
+    }
+    /// Adds a page of older messages. Once the last page arrives, older messages can be loaded again.
+    pub fn receive_history(&mut self, dict: &mut UserDict, payload: Vec<LoadedMessage>, done: bool) {
+        let beginning = payload.len() < REQUEST_LEN;
+        for msg in payload {
+            self.insert(msg, dict);
+        }
+        if beginning {
+            self.before = LoadingState::Finished;
+        } else if done {
+            self.before = LoadingState::Unloaded;
+        }
+        if let Some(date) = self.target {
+            if self.jump_to_date(date) || done {
+                self.target = None;
+            }
+        }
+        self.flag = true;
+    }
+    /// Lets older messages be asked for again after a page of history couldn't be loaded.
+    pub fn history_failed(&mut self) {
+        self.before = LoadingState::Unloaded;
+        self.target = None;
+        self.flag = true;
+    }
+    /// Moves to the first message sent on or after the given time, if enough history is loaded to tell which it is.
+    pub fn jump_to_date(&mut self, date: DateTime<Local>) -> bool {
+        let covered = matches!(self.before, LoadingState::Finished)
+            || self.labels.front().map(|x| x.first_time < date).unwrap_or(false);
+        if !covered {
+            return false;
+        }
+        let pos = (0..self.labels.len())
+            .find(|x| self.visible(*x) && self.labels[*x].first_time >= date)
+            .or_else(|| (0..self.labels.len()).rev().find(|x| self.visible(*x)));
+        if let Some(pos) = pos {
+            self.current = pos;
+            self.current_in_message = 0;
+            self.flag = true;
+        }
+        true
+    }
     /// Provides an extra update towards the beginning
     fn update(&mut self, tasks: &Sender<Task>) {
         if matches!(self.before, LoadingState::Unloaded) {
@@ -451,59 +879,84 @@ impl LoadedMessages {
         } else {
             self.before = LoadingState::Unloaded;
             for msg in payload {
-                self.add_complete(msg, Some(0), dict);
+                self.insert(msg, dict);
             }
         }
     }
-    pub fn receive_update_after(&mut self, dict: &mut UserDict, payload: Vec<LoadedMessage>) {
-        if payload.is_empty() {
-            self.after = LoadingState::Finished;
-        } else {
+    /// Lets older messages be asked for again after a page couldn't be loaded.
+    pub fn update_failed(&mut self) {
+        self.before = LoadingState::Unloaded;
+        self.searching = false;
+        self.flag = true;
+    }
+    /// Leaves the gap after `anchor` open to be filled again after a page couldn't be loaded.
+    pub fn update_after_failed(&mut self, anchor: MessageId) {
+        if self.labels.back().map(|x| x.id <= anchor).unwrap_or(true) {
             self.after = LoadingState::Unloaded;
-            for msg in payload {
-                self.add_complete(msg, None, dict);
-            }
+        } else if let Some(state) = self.gaps.get_mut(&anchor) {
+            *state = LoadingState::Unloaded;
         }
+        self.present = false;
+        self.flag = true;
     }
-    fn beginning_pos(&self, height: usize) -> usize {
-        if height >= self.labels.len() || self.current <= height / 2 {
-            0
-        } else if self.current + height / 2 >= self.labels.len() {
-            self.labels.len() - height
-        } else {
-            self.current - height / 2
+    /// Fills in the gap after `anchor` with the messages that followed it.
+    pub fn receive_update_after(&mut self, dict: &mut UserDict, payload: Vec<LoadedMessage>, anchor: MessageId) {
+        let full = payload.len() >= REQUEST_LEN;
+        let newest = payload.iter().map(|x| x.id).max();
+        if let Some(newest) = newest {
+            // anything loaded in the fetched range that didn't come back has been deleted since
+            let gone: Vec<MessageId> = self
+                .labels
+                .iter()
+                .map(|x| x.id)
+                .filter(|x| *x > anchor && *x <= newest && !payload.iter().any(|y| y.id == *x))
+                .collect();
+            if !gone.is_empty() {
+                self.receive_delete(gone);
+            }
         }
-    }
-    fn beginning_minmax(&self, height: usize, current: usize, len: usize) -> usize {
-        if height >= len || current <= height / 2 {
-            0
-        } else if current + height / 2 >= len {
-            len - height
-        } else {
-            current - height / 2
+        let next = self.labels.iter().map(|x| x.id).find(|x| *x > anchor);
+        self.gaps.remove(&anchor);
+        for msg in payload {
+            self.insert(msg, dict);
+        }
+        match (next, newest) {
+            (None, _) => self.after = if full {LoadingState::Unloaded} else {LoadingState::Finished},
+            (Some(next), Some(newest)) if full && newest < next => {
+                self.gaps.insert(newest, LoadingState::Unloaded);
+            }
+            _ => {} // the gap is closed
         }
+        if self.present && next.is_none() {
+            if let Some(last) = (0..self.labels.len()).rev().find(|x| self.visible(*x)) {
+                self.current = last;
+                self.current_in_message = 0;
+            }
+            self.present = !matches!(self.after, LoadingState::Finished);
+        }
+        self.flag = true;
     }
-    fn count(&self, grid: &Grid, pos: usize) -> usize {
+    fn count(&self, grid: &Grid, pos: usize, dict: &UserDict) -> usize {
         let len = grid.len_messages();
         let mut result = 1; //for the username
-        for line in &self.labels[pos].content.content {
-            if line.is_empty() {
-                result += 1;
-            } else {
-                result += (line.len() - 1) / len + 1;
-            }
+        if self.labels[pos].reply.is_some() {
+            result += 1;
         }
-        result += self.labels[pos].content.attachments.len();
+        result += self.labels[pos].body(len, dict).len();
         result
     }
-    pub fn attachment_pos(&self, grid: &Grid) -> Option<usize> {
-        let len = self.labels[self.current].content.attachment_url.len();
+    /// The number of lines shown after a message's attachments.
+    fn footer(&self, pos: usize) -> usize {
+        if self.labels[pos].reactions.is_empty() {0} else {1}
+    }
+    pub fn attachment_pos(&self, grid: &Grid, dict: &UserDict) -> Option<usize> {
+        let len = self.labels[self.current].content.file_paths().len();
         if len == 0 {
             None
         } else {
             Some(
-                (self.current_in_message + len)
-                    .checked_sub(self.count(grid, self.current))
+                (self.current_in_message + len + self.footer(self.current))
+                    .checked_sub(self.count(grid, self.current, dict))
                     .unwrap_or(0)
                     .min(len - 1),
             )
@@ -513,11 +966,26 @@ impl LoadedMessages {
         let u_id = self.labels[self.current].user;
         block_on(u_id.create_dm_channel(Arc::clone(&client.cache_and_http)))
     }
-    pub fn open(&self, options: &ExtConfig, grid: &Grid) {
-        if let Some(val) = self.attachment_pos(grid) {
-            options.open(&self.labels[self.current].content.attachment_url[val]);
-        } else {
-            panic!("NO FILES!")
+    /// Moves the cursor to the line drawn on a row of the pane.
+    /// Returns the path of the attachment on that line, if there is one.
+    pub fn click(&mut self, row: usize, grid: &Grid, dict: &UserDict) -> Option<String> {
+        let (id, line) = (*self.rows.get(row)?)?;
+        let pos = self.labels.iter().position(|x| x.id == id)?;
+        self.current = pos;
+        self.current_in_message = line;
+        self.flag = true;
+        let files = self.labels[pos].content.file_paths();
+        let first = (self.count(grid, pos, dict) - self.footer(pos)).checked_sub(files.len())?;
+        line.checked_sub(first).and_then(|x| files.get(x)).map(|x| x.to_string())
+    }
+    /// Opens the file under the cursor. Returns false if the message has none.
+    pub fn open(&self, options: &ExtConfig, grid: &Grid, dict: &UserDict) -> bool {
+        match self.attachment_pos(grid, dict) {
+            Some(val) => {
+                options.open(self.labels[self.current].content.file_paths()[val]);
+                true
+            }
+            None => false,
         }
     }
 }
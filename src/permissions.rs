@@ -0,0 +1,52 @@
+use serenity::model::{
+    channel::{GuildChannel, PermissionOverwriteType},
+    guild::Role,
+    id::{RoleId, UserId},
+    Permissions,
+};
+
+/// The logged-in user's roles in a guild, used to work out what they can do in its channels.
+pub struct Membership {
+    pub everyone: Permissions,
+    pub roles: Vec<(RoleId, Permissions)>,
+    pub owner: bool,
+}
+impl Membership {
+    pub fn new(roles: &[Role], mine: &[RoleId], everyone: RoleId, owner: bool) -> Membership {
+        Membership {
+            everyone: roles.iter().find(|x| x.id == everyone).map(|x| x.permissions).unwrap_or_else(Permissions::empty),
+            roles: roles.iter().filter(|x| mine.contains(&x.id)).map(|x| (x.id, x.permissions)).collect(),
+            owner,
+        }
+    }
+    /// The user's permissions in a channel, following Discord's order of overwrites:
+    /// @everyone, then every role at once, then the user themself. Owners can do anything.
+    pub fn permissions(&self, channel: &GuildChannel, me: UserId) -> Permissions {
+        if self.owner {
+            return Permissions::all();
+        }
+        let mut result = self.roles.iter().fold(self.everyone, |x, y| x | y.1);
+        if result.contains(Permissions::ADMINISTRATOR) {
+            return Permissions::all();
+        }
+        let everyone = RoleId(channel.guild_id.0);
+        let overwrites = &channel.permission_overwrites;
+        for line in overwrites.iter().filter(|x| x.kind == PermissionOverwriteType::Role(everyone)) {
+            result = (result & !line.deny) | line.allow;
+        }
+        let (mut allow, mut deny) = (Permissions::empty(), Permissions::empty());
+        for line in overwrites {
+            if let PermissionOverwriteType::Role(id) = line.kind {
+                if self.roles.iter().any(|x| x.0 == id) {
+                    allow |= line.allow;
+                    deny |= line.deny;
+                }
+            }
+        }
+        result = (result & !deny) | allow;
+        for line in overwrites.iter().filter(|x| x.kind == PermissionOverwriteType::Member(me)) {
+            result = (result & !line.deny) | line.allow;
+        }
+        result
+    }
+}
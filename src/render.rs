@@ -2,6 +2,32 @@ use std::io::Stdout;
 
 use crate::input::Context;
 
+const MIN_PANE: usize = 8; // the narrowest a list can be made, leaving room for its markers
+const MIN_MESSAGES: usize = 20;
+const RESIZE_STEP: usize = 4;
+
+/// The first item drawn in a pane of `height` rows, keeping the item at `current` near the middle.
+pub fn scroll_start(current: usize, len: usize, height: usize) -> usize {
+    if height >= len || current <= height / 2 {
+        0
+    } else if current + height / 2 >= len {
+        len - height
+    } else {
+        current - height / 2
+    }
+}
+/// The item drawn on a row of a pane, found the same way the pane was drawn.
+pub fn item_at(current: usize, len: usize, height: usize, row: usize) -> Option<usize> {
+    Some(scroll_start(current, len, height) + row).filter(|x| *x < len)
+}
+/// How the columns are arranged, which is kept between sessions.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Default)]
+pub struct Layout {
+    pub widths: Option<[usize; 3]>, // of the server, category and channel lists, or None to fit the terminal
+    pub hide_servers: bool,
+    pub hide_categories: bool,
+    pub focus: bool, // the message pane takes the whole width
+}
 pub struct Grid {
     pub start_y: usize,
     pub border_y: usize,
@@ -13,22 +39,116 @@ pub struct Grid {
     pub border_3: usize,    // channels -> messages
     pub end_x: usize,       // messages -> end of screen
     pub context: Context,
+    pub layout: Layout,
 }
 impl Grid {
-    pub fn new(max_x: usize, max_y: usize) -> Grid {
-        Grid {
+    pub fn new(max_x: usize, max_y: usize, layout: Layout) -> Grid {
+        let mut grid = Grid {
             start_y: 0,
             border_y: (max_y - 1) as usize,
             end_y: max_y as usize,
             max_box_len: 15.min(max_y / 2).max(1) as usize,
             start_x: 0,
-            border_1: 25.min((max_x / 5) as usize),
-            border_2: 50.min((max_x * 2 / 5) as usize),
-            border_3: 75.min((max_x * 3 / 5) as usize),
+            border_1: 0,
+            border_2: 0,
+            border_3: 0,
             end_x: max_x as usize,
             context: Context::Server,
+            layout,
+        };
+        grid.columns();
+        grid.show_focused();
+        grid
+    }
+    /// The widths of the server, category and channel lists when none are hidden.
+    fn widths(&self) -> [usize; 3] {
+        let max_x = self.end_x - self.start_x;
+        let fit = [
+            25.min(max_x / 5),
+            50.min(max_x * 2 / 5) - 25.min(max_x / 5),
+            75.min(max_x * 3 / 5) - 50.min(max_x * 2 / 5),
+        ];
+        match self.layout.widths {
+            // widths that no longer fit are ignored until the terminal is big enough again
+            Some(val) if val.iter().sum::<usize>() + MIN_MESSAGES <= max_x => val,
+            _ => fit,
+        }
+    }
+    /// Places the borders between the columns according to the layout.
+    fn columns(&mut self) {
+        let [servers, categories, channels] = self.widths();
+        let focus = self.layout.focus;
+        self.border_1 = self.start_x + if focus || self.layout.hide_servers {0} else {servers};
+        self.border_2 = self.border_1 + if focus || self.layout.hide_categories {0} else {categories};
+        self.border_3 = self.border_2 + if focus {0} else {channels};
+    }
+    pub fn visible(&self, context: Context) -> bool {
+        match context {
+            Context::Server => self.len_servers() > 0,
+            Context::Category => self.len_categories() > 0,
+            Context::Channel => self.len_channels() > 0,
+            Context::Message => true,
+        }
+    }
+    /// Moves the focus rightwards off any hidden pane.
+    pub fn show_focused(&mut self) {
+        while !self.visible(self.context) {
+            self.context = match self.context {
+                Context::Server => Context::Category,
+                Context::Category => Context::Channel,
+                _ => Context::Message,
+            };
         }
     }
+    /// Widens or narrows the focused pane. The message pane takes the difference
+    /// from the channel list, since it fills whatever is left.
+    /// Returns false if the pane can't be resized any further.
+    pub fn resize(&mut self, wider: bool) -> bool {
+        if self.layout.focus {
+            return false;
+        }
+        let mut widths = self.widths();
+        let (pos, grow) = match self.context {
+            Context::Server => (0, wider),
+            Context::Category => (1, wider),
+            Context::Channel => (2, wider),
+            Context::Message => (2, !wider),
+        };
+        widths[pos] = if grow {
+            widths[pos] + RESIZE_STEP
+        } else {
+            match widths[pos].checked_sub(RESIZE_STEP) {
+                Some(val) if val >= MIN_PANE => val,
+                _ => return false,
+            }
+        };
+        if widths.iter().sum::<usize>() + MIN_MESSAGES > self.end_x - self.start_x {
+            return false;
+        }
+        self.layout.widths = Some(widths);
+        self.columns();
+        true
+    }
+    pub fn toggle_servers(&mut self) {
+        self.layout.hide_servers = !self.layout.hide_servers;
+        self.columns();
+        self.show_focused();
+    }
+    pub fn toggle_categories(&mut self) {
+        self.layout.hide_categories = !self.layout.hide_categories;
+        self.columns();
+        self.show_focused();
+    }
+    pub fn toggle_focus(&mut self) {
+        self.layout.focus = !self.layout.focus;
+        self.columns();
+        self.show_focused();
+    }
+    /// Goes back to widths that fit the terminal, with every pane shown.
+    pub fn reset_layout(&mut self) {
+        self.layout = Layout::default();
+        self.columns();
+    }
     pub fn len_servers(&self) -> usize {
         self.border_1
     }
@@ -59,6 +179,18 @@ impl Grid {
     pub fn messages_selected(&self) -> bool {
         matches!(self.context, Context::Message)
     }
+    /// The pane drawn at a column.
+    pub fn pane_at(&self, x: usize) -> Context {
+        if x < self.border_1 {
+            Context::Server
+        } else if x < self.border_2 {
+            Context::Category
+        } else if x < self.border_3 {
+            Context::Channel
+        } else {
+            Context::Message
+        }
+    }
     pub fn total_across(&self) -> usize {
         self.end_x - self.start_x
     }
@@ -66,12 +198,107 @@ impl Grid {
         self.border_y = self.end_y - lines;
         self.end_y = max_y;
         self.max_box_len = 15.min(max_y / 2).max(1);
-        self.border_1 = 25.min(max_x / 5);
-        self.border_2 = 50.min(max_x * 2 / 5);
-        self.border_3 = 75.min(max_x * 3 / 5);
         self.end_x = max_x;
+        self.columns();
     }
     pub fn update_msg(&mut self, lines: usize) {
         self.border_y = self.end_y - lines;
     }
-}
\ No newline at end of file
+}#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(context: Context) -> Grid {
+        let mut grid = Grid::new(100, 40, Layout::default());
+        grid.context = context;
+        grid
+    }
+
+    #[test]
+    fn panes_fit_the_terminal_by_default() {
+        let grid = grid(Context::Server);
+        assert_eq!(grid.widths(), [20, 20, 20]);
+        assert_eq!(grid.len_messages(), 40);
+    }
+
+    #[test]
+    fn narrowing_stops_at_the_narrowest_pane() {
+        let mut grid = grid(Context::Server);
+        for _ in 0..3 {
+            assert!(grid.resize(false));
+        }
+        assert_eq!(grid.len_servers(), MIN_PANE);
+        assert!(!grid.resize(false));
+        assert_eq!(grid.len_servers(), MIN_PANE);
+    }
+
+    #[test]
+    fn widening_leaves_room_for_messages() {
+        let mut grid = grid(Context::Channel);
+        while grid.resize(true) {}
+        assert_eq!(grid.len_channels(), 40);
+        assert!(grid.len_messages() >= MIN_MESSAGES);
+        assert!(grid.len_messages() < MIN_MESSAGES + RESIZE_STEP);
+    }
+
+    #[test]
+    fn widening_messages_narrows_channels() {
+        let mut grid = grid(Context::Message);
+        assert!(grid.resize(true));
+        assert_eq!(grid.len_channels(), 16);
+        assert_eq!(grid.len_messages(), 44);
+    }
+
+    #[test]
+    fn nothing_is_resized_in_focus_mode() {
+        let mut grid = grid(Context::Message);
+        grid.toggle_focus();
+        assert!(!grid.resize(true));
+        assert_eq!(grid.len_messages(), 100);
+    }
+
+    #[test]
+    fn saved_widths_that_dont_fit_are_ignored() {
+        let layout = Layout { widths: Some([40, 40, 40]), ..Default::default() };
+        let grid = Grid::new(100, 40, layout);
+        assert_eq!(grid.widths(), [20, 20, 20]);
+    }
+
+    #[test]
+    fn focus_moves_off_hidden_panes() {
+        let mut grid = grid(Context::Server);
+        grid.toggle_servers();
+        assert!(grid.context == Context::Category);
+        grid.toggle_categories();
+        assert!(grid.context == Context::Channel);
+        grid.toggle_focus();
+        assert!(grid.context == Context::Message);
+        grid.reset_layout();
+        grid.show_focused();
+        assert!(grid.context == Context::Message);
+    }
+
+    #[test]
+    fn columns_map_back_to_panes() {
+        let grid = grid(Context::Server);
+        assert!(grid.pane_at(0) == Context::Server);
+        assert!(grid.pane_at(20) == Context::Category);
+        assert!(grid.pane_at(59) == Context::Channel);
+        assert!(grid.pane_at(60) == Context::Message);
+    }
+
+    #[test]
+    fn scrolling_keeps_the_cursor_near_the_middle() {
+        assert_eq!(scroll_start(3, 5, 10), 0);
+        assert_eq!(scroll_start(4, 100, 10), 0);
+        assert_eq!(scroll_start(50, 100, 10), 45);
+        assert_eq!(scroll_start(98, 100, 10), 90);
+    }
+
+    #[test]
+    fn clicks_find_the_drawn_item() {
+        assert_eq!(item_at(50, 100, 10, 0), Some(45));
+        assert_eq!(item_at(98, 100, 10, 9), Some(99));
+        assert_eq!(item_at(0, 3, 10, 5), None);
+    }
+}
@@ -3,11 +3,11 @@ use std::{collections::VecDeque, fs, io::stdout, path::PathBuf, sync::mpsc::{Rec
 use crate::{messages::LoadingState, task::Control};
 use serenity::{Client, model::{
     channel::{Channel, GuildChannel},
-    id::{GuildId},
+    id::{GuildId, MessageId},
 }};
 use serde_json::to_string;
 use serde_json::from_str;
-use crate::{categories::{Categories, CategoryLabel}, channels::{ChannelLabel, Channels}, render::Grid, input::{Parser, Response, State}, message::{LoadedMessage, UserDict}, messages::{LoadedMessages, Messages}, servers::{ServerLabel, Servers, Unread}, task::{Product, Task}, textbox::Textbox};
+use crate::{categories::{Categories, CategoryLabel}, channels::{ChannelLabel, Channels}, render::{Grid, Layout}, input::{Parser, REQUEST_LEN, Response, State}, message::{LoadedMessage, UserDict}, messages::{LoadedMessages, Messages}, servers::{ServerLabel, Servers, Unread}, task::{Product, Task}, textbox::Textbox};
 
 pub const SAVE_DIR:&str = "save";
 
@@ -37,12 +37,15 @@ pub fn save(parse: &Parser) -> Result<(), String>{
 pub struct ParserSave {
     pub user_dict: UserDict,
     pub servers: ServerSave,
+    #[serde(default)]
+    pub layout: Layout,
 }
 impl ParserSave {
     pub fn process(orig: &Parser) -> ParserSave {
         ParserSave {
             user_dict: orig.int.user_dict.clone(),
             servers: ServerSave::process(&orig.servers),
+            layout: orig.int.grid.layout.clone(),
         }
     }
 }
@@ -60,7 +63,9 @@ impl ServerSave {
             contents: servers.contents.iter().map(|x| CategorySave::process(x)).collect()
         }
     }
-    pub fn reload(self) -> Servers {
+    pub fn reload(mut self) -> Servers {
+        // older saves have an extra entry here
+        self.unread.resize(self.labels.len(), Unread::Read);
         Servers {
             labels: self.labels,
             unread: self.unread,
@@ -168,6 +173,10 @@ pub struct LoadedMessagesSave {
     pub current: usize,
     pub current_in_message: usize,
     pub selected: usize,
+    #[serde(default)]
+    pub gaps: Vec<MessageId>,
+    #[serde(default)]
+    pub bookmarks: Vec<(String, MessageId)>,
 }
 impl LoadedMessagesSave {
     pub fn process(loaded_messsages: &LoadedMessages) -> LoadedMessagesSave {
@@ -175,13 +184,19 @@ impl LoadedMessagesSave {
             labels: loaded_messsages.labels.clone(),
             unread: loaded_messsages.unread,
             id: loaded_messsages.id.clone(),
-            more_before: matches!(loaded_messsages.before, LoadingState::Unloaded),
+            // a page still loading when the client closes is asked for again next time
+            more_before: !matches!(loaded_messsages.before, LoadingState::Finished),
             current: loaded_messsages.current,
             current_in_message: loaded_messsages.current_in_message,
             selected: loaded_messsages.selected,
+            gaps: loaded_messsages.gaps.keys().copied().collect(),
+            bookmarks: loaded_messsages.bookmarks.clone(),
         }
     }
     pub fn reload(self) -> LoadedMessages {
+        let unindexed = self.labels.iter().map(|x| x.id).collect();
+        // the newest saved messages may have been edited or deleted while the client was closed
+        let refresh = self.labels.get(self.labels.len().saturating_sub(REQUEST_LEN)).map(|x| x.id);
         LoadedMessages {
             labels: self.labels,
             unread: self.unread,
@@ -192,6 +207,16 @@ impl LoadedMessagesSave {
             selected: self.selected,
             after: LoadingState::Unloaded,
             flag: true,
+            hide_deleted: false,
+            search: None,
+            searching: false,
+            unindexed,
+            gaps: self.gaps.into_iter().map(|x| (x, LoadingState::Unloaded)).collect(),
+            target: None,
+            bookmarks: self.bookmarks,
+            rows: Vec::new(),
+            refresh,
+            present: false,
         }
     }
 }
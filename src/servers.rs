@@ -1,10 +1,10 @@
-use std::io::Stdout;
+use std::{collections::HashSet, io::Stdout};
 
 use crossterm::{queue, style::Print};
-use serenity::model::{channel::{Channel, Message}, id::{ChannelId, GuildId}};
+use serenity::model::{channel::{Channel, ChannelType, GuildChannel, Message}, id::{ChannelId, GuildId}, voice::VoiceState};
 use unicode_segmentation::UnicodeSegmentation;
 
-use crate::{ansi, categories::Categories, channels::Channels, colors::SimpleColor, render::Grid, messages::Messages};
+use crate::{ansi, categories::Categories, channels::{self, ChannelLabel, Channels}, colors::SimpleColor, render::{self, Grid}, messages::Messages};
 const DEFAULT: &str = "uncategorized channels";
 
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
@@ -14,6 +14,37 @@ pub enum Unread {
     Mentions(u64),
     Gone,
 }
+impl Unread {
+    /// Records one more incoming message.
+    pub fn bump(&mut self, mentioned: bool) {
+        *self = match self {
+            Unread::Read | Unread::Unread if mentioned => Unread::Mentions(1),
+            Unread::Read | Unread::Unread => Unread::Unread,
+            Unread::Mentions(val) if mentioned => Unread::Mentions(*val + 1),
+            Unread::Mentions(val) => Unread::Mentions(*val),
+            Unread::Gone => Unread::Gone,
+        }
+    }
+    /// Combines the states of everything inside of a category or server.
+    pub fn total(states: &[Unread]) -> Unread {
+        let mut unread = false;
+        let mut mentions = 0;
+        for line in states {
+            match line {
+                Unread::Unread => unread = true,
+                Unread::Mentions(val) => mentions += val,
+                Unread::Read | Unread::Gone => {}
+            }
+        }
+        if mentions > 0 {
+            Unread::Mentions(mentions)
+        } else if unread {
+            Unread::Unread
+        } else {
+            Unread::Read
+        }
+    }
+}
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct ServerLabel {
     name: String,
@@ -26,6 +57,12 @@ impl ServerLabel {
             color: SimpleColor::new(),
         }
     }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn rename(&mut self, name: String) {
+        self.name = name;
+    }
     pub fn to_string(&self) -> String {
         self.color.to_ansi_value() + &self.name 
     }
@@ -42,7 +79,7 @@ impl Servers {
     pub fn new() -> Self {
         Self {
             labels: vec![ServerLabel::new("serverless channels".to_string())],
-            unread: vec![Unread::Read, Unread::Read],
+            unread: vec![Unread::Read],
             contents: vec![Categories::new("DMs", None)],
             current: 0,
             selected: 0,
@@ -52,18 +89,6 @@ impl Servers {
     pub fn flag(&mut self) {
         self.flag = true;
     }
-    pub fn up(&mut self) {
-        if self.current > 0 {
-            self.current -= 1;
-            self.flag = true;
-        }
-    }
-    pub fn down(&mut self) {
-        if self.current < self.labels.len() - 1 {
-            self.current += 1;
-            self.flag = true;
-        }
-    }
     pub fn select(&mut self) {
         self.selected = self.current;
         self.flag = true;
@@ -153,7 +178,7 @@ impl Servers {
         }
     }
     fn draw_real(&mut self, grid: &Grid, out: &mut Stdout) {
-        let start = self.beginning_pos(grid.height());
+        let start = render::scroll_start(self.current, self.labels.len(), grid.height());
         let sample = " ".graphemes(true).cycle();
         for i in start..start + grid.height() {
             let val: String = self
@@ -212,46 +237,282 @@ impl Servers {
             let _ = queue!(out, Print(crate::ansi::RESET.to_string(),));
         }
     }
-    fn beginning_pos(&self, height: usize) -> usize {
-        if height >= self.labels.len() || self.current <= height / 2 {
-            0
-        } else if self.current + height / 2 >= self.labels.len() {
-            self.labels.len() - height
-        } else {
-            self.current - height / 2
+    /// Moves the cursor to the item drawn on a row of the pane.
+    pub fn click(&mut self, row: usize, height: usize) {
+        if let Some(pos) = render::item_at(self.current, self.labels.len(), height, row) {
+            self.current = pos;
+            self.flag = true;
+        }
+    }
+    /// The messages of a channel, if it's listed.
+    pub fn find_channel(&mut self, channel: ChannelId, guild: Option<GuildId>) -> Option<&mut Messages> {
+        let (server, category, channel) = self.find_pos(channel, guild).ok()?;
+        Some(self.grab3(server, category, channel))
+    }
+    fn find_server(&self, guild: GuildId) -> Option<usize> {
+        self.contents.iter().position(|x| x.s_id == Some(guild))
+    }
+    /// Lists a server, or renames it and brings its channels up to date if it's already listed.
+    pub fn update_guild(&mut self, guild: GuildId, name: String, mut channels: Vec<GuildChannel>) {
+        match self.find_server(guild) {
+            Some(pos) => {
+                self.labels[pos].rename(name);
+                if matches!(self.unread[pos], Unread::Gone) {
+                    let total = Unread::total(&self.contents[pos].unread);
+                    self.mark(pos, total);
+                }
+                self.flag = true;
+            }
+            None => self.add(name, None, guild),
+        }
+        // categories go first, so that their channels have somewhere to go
+        channels.sort_by_key(|x| (x.kind != ChannelType::Category, x.position));
+        for channel in channels {
+            self.update_channel(channel);
+        }
+    }
+    /// Stops listing a server that was left, or marks it as gone if it's only unavailable.
+    pub fn remove_guild(&mut self, guild: GuildId, unavailable: bool) {
+        if let Some(pos) = self.find_server(guild) {
+            if unavailable {
+                self.mark(pos, Unread::Gone);
+            } else {
+                self.remove(pos);
+            }
+        }
+    }
+    /// Lists a channel or category, or renames and moves it if it's already listed.
+    pub fn update_channel(&mut self, channel: GuildChannel) {
+        let server = match self.find_server(channel.guild_id) {
+            Some(val) => val,
+            None => return,
+        };
+        if channel.kind == ChannelType::Category {
+            return self.grab(server).update(channel);
+        }
+        if !channels::is_listed(channel.kind) {
+            return;
+        }
+        let target = self.contents[server].find(channel.category_id);
+        let (category, pos) = match self.find_pos(channel.id, Some(channel.guild_id)) {
+            Ok((_, category, pos)) => (category, pos),
+            Err(_) => {
+                let channels = self.grab2(server, target);
+                let pos = channels.position_for(channel.position);
+                channels.add(channel.name.clone(), Some(pos), Channel::Guild(channel));
+                return;
+            }
+        };
+        let channels = self.grab2(server, category);
+        if matches!(channels.unread[pos], Unread::Gone) {
+            channels.mark(pos, Unread::Read);
+        }
+        let moved = category != target
+            || !matches!(channels.contents[pos].id(), Some(Channel::Guild(val)) if val.position == channel.position);
+        channels.labels[pos].rename(channel.name.clone());
+        channels.contents[pos].set_id(Channel::Guild(channel.clone()));
+        channels.flag();
+        if moved {
+            self.move_channel((server, category, pos), target, channel.position);
         }
     }
-    pub fn find_channel(&mut self, channel: ChannelId, guild: Option<GuildId>) -> &mut Messages {
-        if let Some(server) = self
+    /// Moves a channel and its threads, keeping it open if it was.
+    fn move_channel(&mut self, (server, category, pos): (usize, usize, usize), target: usize, position: i64) {
+        let open = self.selected == server && self.contents[server].selected == category && self.contents[server].contents[category].selected == pos;
+        let items = self.grab2(server, category).take(pos);
+        self.refresh(server, category);
+        let channels = self.grab2(server, target);
+        let pos = channels.position_for(position);
+        channels.put(pos, items);
+        self.refresh(server, target);
+        if open {
+            let categories = self.grab(server);
+            categories.selected = target;
+            categories.current = target;
+            categories.flag();
+            let channels = categories.grab(target);
+            channels.selected = pos;
+            channels.current = pos;
+        }
+    }
+    /// Brings a saved tree up to date with the servers that exist now, marking the ones that were left as gone.
+    /// Their channels are brought up to date by `sync_guild` as each server arrives from the gateway.
+    pub fn reconcile(&mut self, guilds: Vec<(GuildId, String)>) {
+        let listed: HashSet<GuildId> = guilds.iter().map(|x| x.0).collect();
+        for (guild, name) in guilds {
+            self.update_guild(guild, name, Vec::new());
+        }
+        let unavailable = (1..self.contents.len())
+            .filter(|&x| self.contents[x].s_id.map(|x| !listed.contains(&x)).unwrap_or(false))
+            .collect::<Vec<usize>>();
+        for server in unavailable {
+            self.mark(server, Unread::Gone);
+        }
+    }
+    /// Brings a server up to date with its full list of channels, marking any that are missing from it as gone.
+    pub fn sync_guild(&mut self, guild: GuildId, name: String, channels: Vec<GuildChannel>) {
+        let existing: HashSet<ChannelId> = channels.iter().map(|x| x.id).collect();
+        self.update_guild(guild, name, channels);
+        let server = match self.find_server(guild) {
+            Some(val) => val,
+            None => return,
+        };
+        for category in 0..self.contents[server].contents.len() {
+            let channels = self.grab2(server, category);
+            for pos in 1..channels.labels.len() {
+                // threads aren't listed with the other channels
+                let gone = !channels.labels[pos].thread
+                    && channels.contents[pos].id().map(|x| !existing.contains(&x.id())).unwrap_or(false);
+                if gone {
+                    channels.mark(pos, Unread::Gone);
+                }
+            }
+            self.refresh(server, category);
+        }
+        let categories = self.grab(server);
+        for category in 1..categories.labels.len() {
+            if categories.contents[category].id.as_ref().map(|x| !existing.contains(&x.id)).unwrap_or(false) {
+                categories.mark(category, Unread::Gone);
+            }
+        }
+    }
+    /// Marks a deleted channel as gone. The channels of a deleted category become uncategorized.
+    pub fn delete_channel(&mut self, channel: GuildChannel) {
+        let server = match self.find_server(channel.guild_id) {
+            Some(val) => val,
+            None => return,
+        };
+        if channel.kind == ChannelType::Category {
+            let category = match self.contents[server].find(Some(channel.id)) {
+                0 => return,
+                val => val,
+            };
+            while self.contents[server].contents[category].labels.len() > 1 {
+                let channels = self.grab2(server, category);
+                let position = match channels.contents[1].id() {
+                    Some(Channel::Guild(val)) => val.position,
+                    _ => 0,
+                };
+                let items = channels.take(1);
+                let channels = self.grab2(server, 0);
+                let pos = channels.position_for(position);
+                channels.put(pos, items);
+            }
+            self.grab(server).remove(category);
+            self.refresh(server, 0);
+        } else if let Ok((server, category, pos)) = self.find_pos(channel.id, Some(channel.guild_id)) {
+            self.grab2(server, category).mark(pos, Unread::Gone);
+            self.refresh(server, category);
+        }
+    }
+    /// Finds the server, category and channel indices of a channel.
+    pub fn find_pos(&self, channel: ChannelId, guild: Option<GuildId>) -> Result<(usize, usize, usize), &'static str> {
+        let server = self
             .contents
             .iter()
             .position(|x| x.s_id == guild)
-        {
-            let grabbed = self.grab(server);
-            let mut category = None;
-            let mut ch = None;
-            for (i, mut item) in grabbed
-                .contents
-                .iter()
-                .map(|x| x.contents.iter())
-                .enumerate()
-            {
-                if let Some(val) = item.position(|x| {
-                    x.id()
-                        .and_then(|x| Some(x.id() == channel))
-                        .unwrap_or(false)
-                }) {
-                    category = Some(i);
-                    ch = Some(val);
+            .ok_or("No server found!")?;
+        for (i, item) in self.contents[server].contents.iter().enumerate() {
+            if let Some(val) = item.contents.iter().position(|x| {
+                x.id()
+                    .map(|x| x.id() == channel)
+                    .unwrap_or(false)
+            }) {
+                return Ok((server, i, val));
+            }
+        }
+        Err("No channel/category found!")
+    }
+    pub fn is_thread(&self, (server, category, channel): (usize, usize, usize)) -> bool {
+        self.contents[server].contents[category].labels[channel].thread
+    }
+    /// Lists a thread under its channel, or updates it if it's already listed.
+    pub fn add_thread(&mut self, thread: GuildChannel) {
+        let guild = Some(thread.guild_id);
+        if let Ok((server, category, channel)) = self.find_pos(thread.id, guild) {
+            let channels = self.grab2(server, category);
+            channels.labels[channel].rename(thread.name.clone());
+            channels.flag();
+            return;
+        }
+        let parent = match thread.category_id {
+            Some(val) => val,
+            None => return,
+        };
+        if let Ok((server, category, channel)) = self.find_pos(parent, guild) {
+            self.grab2(server, category).add_thread(thread.name.clone(), channel, thread);
+        }
+    }
+    /// Moves a user to the voice channel they joined, or out of voice if they left.
+    pub fn voice_update(&mut self, state: &VoiceState, name: String) {
+        let server = match self.contents.iter().position(|x| x.s_id == state.guild_id) {
+            Some(val) => val,
+            None => return,
+        };
+        for channels in self.contents[server].contents.iter_mut() {
+            for label in channels.labels.iter_mut() {
+                let len = label.connected.len();
+                label.connected.retain(|x| x.0 != state.user_id);
+                if label.connected.len() != len {
+                    channels.flag = true;
                 }
             }
-            if let (Some(category), Some(channel)) = (category, ch) {
-                return self.grab3(server, category, channel);
-            } else {
-                panic!("No channel/category found!");
+        }
+        if let Some(channel) = state.channel_id {
+            if let Ok((server, category, channel)) = self.find_pos(channel, state.guild_id) {
+                let channels = self.grab2(server, category);
+                channels.labels[channel].connected.push((state.user_id, name));
+                channels.flag();
             }
-        } else {
-            panic!("No server found!");
         }
     }
+    /// Marks every loaded channel as possibly missing its newest messages.
+    pub fn stale(&mut self) {
+        for categories in self.contents.iter_mut() {
+            for channels in categories.contents.iter_mut() {
+                for messages in channels.contents.iter_mut() {
+                    if let Messages::Loaded(val) = messages {
+                        val.stale();
+                    }
+                }
+            }
+        }
+    }
+    /// The label of the channel whose messages are being shown.
+    pub fn selected_label(&self) -> &ChannelLabel {
+        let categories = &self.contents[self.selected];
+        let channels = &categories.contents[categories.selected];
+        &channels.labels[channels.selected]
+    }
+    /// The indices of the channel whose messages are being shown.
+    pub fn selected_pos(&mut self) -> (usize, usize, usize) {
+        let category = self.get().selected;
+        let channel = self.get2().selected;
+        (self.selected, category, channel)
+    }
+    /// Records a new message in a channel, updating the category and server above it.
+    /// Returns true if the channel didn't have any unread messages before.
+    pub fn notify(&mut self, (server, category, channel): (usize, usize, usize), mentioned: bool) -> bool {
+        let channels = self.grab2(server, category);
+        let first = matches!(channels.unread[channel], Unread::Read);
+        let mut state = channels.unread[channel].clone();
+        state.bump(mentioned);
+        channels.mark(channel, state);
+        self.refresh(server, category);
+        first
+    }
+    /// Marks a channel as read, updating the category and server above it.
+    pub fn clear(&mut self, (server, category, channel): (usize, usize, usize)) {
+        let channels = self.grab2(server, category);
+        if matches!(channels.unread[channel], Unread::Unread | Unread::Mentions(_)) {
+            channels.mark(channel, Unread::Read);
+            self.refresh(server, category);
+        }
+    }
+    fn refresh(&mut self, server: usize, category: usize) {
+        let total = Unread::total(&self.grab2(server, category).unread);
+        self.grab(server).mark(category, total);
+        let total = Unread::total(&self.grab(server).unread);
+        self.mark(server, total);
+    }
 }
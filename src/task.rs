@@ -1,22 +1,42 @@
 use std::{task::{RawWaker, Waker}, thread::sleep, time::Duration};
+use chrono::{DateTime, Local};
 use std::sync::mpsc::{Receiver, Sender, channel};
 use futures::{future::join_all, stream::futures_unordered::FuturesUnordered};
 use futures::stream::StreamExt;
 use futures::stream::Stream;
-use serenity::{Client, framework::StandardFramework, model::{channel::{Attachment, Channel, Message}, id::MessageId}};
+use serenity::{Client, framework::StandardFramework, http::AttachmentType, model::{channel::{Attachment, Channel, GuildChannel, Message}, guild::{Emoji, Role}, id::{ChannelId, GuildId, MessageId, RoleId, UserId}, user::User}};
 
-use crate::{DummyHandler, block_on::{self, block_on}, file::{fs_write_2}, input::Response, message::LoadedMessage, messages::LoadedMessages};
+use crate::{DummyHandler, api, block_on::{self, block_on}, file::{fs_write_2}, input::{REQUEST_LEN, Response}, message::LoadedMessage, messages::LoadedMessages};
 pub enum Control {
     Drain,
     Kill,
 }
+/// How far back a history request goes.
+#[derive(Clone, Copy)]
+pub enum Until {
+    Pages(usize),
+    Date(DateTime<Local>),
+}
+/// The pause between pages of history. Serenity's ratelimiter already waits out exhausted buckets;
+/// this just keeps long fetches from using up the bucket other requests share.
+const PAGE_DELAY: Duration = Duration::from_millis(250);
 pub enum Task {
     Download(Attachment, String),
+    /// Downloads a file from anywhere else, such as an embed's image.
+    DownloadUrl(String, String),
     GetMessagesBefore(Channel, MessageId),
     GetMessagesAfter(Channel, MessageId),
     GetNewMessages(Channel),
+    /// Pages back through a channel's history from before the given message, one page per task.
+    GetHistory(Channel, MessageId, Until),
     /// Kicks a "can" (response) down the road (waits a duration) until the program is equipped to handle it. 
     Kick(Response, Duration),
+    /// Sends a message with the files at the given paths attached.
+    Upload(ChannelId, serde_json::Value, Vec<String>),
+    GetUser(UserId),
+    GetArchivedThreads(ChannelId),
+    /// Looks up a server's active threads, its roles, its emojis and the user's own roles in it.
+    GetGuildInfo(GuildId, UserId),
 }
 impl Task {
     pub async fn execute(self, client: &Client) -> (Option<Product>, Vec<Task>) {
@@ -27,16 +47,25 @@ impl Task {
                 fs_write_2(file, &location);
                 (None, v)
             }
+            Task::DownloadUrl(url, location) => {
+                let file = api::download(&url).await.unwrap_or(b"Could not find file!".to_vec());
+                fs_write_2(file, &location);
+                (None, v)
+            }
             Task::GetMessagesBefore(channel, search) => {
                 match channel.clone() {
                     Channel::Guild(ch) => {
-                        let result = ch.messages(client.cache_and_http.http.clone(), |x| x.before(search.0)).await.unwrap_or(vec![]);
-                        let result = result.into_iter().map(|x| LoadedMessage::from_message(x, &mut v)).collect::<Vec<_>>();
+                        // an empty page would close the gap for good
+                        let result = ch.messages(client.cache_and_http.http.clone(), |x| x.before(search.0)).await
+                            .map(|x| x.into_iter().map(|x| LoadedMessage::from_message(x, &mut v)).collect::<Vec<_>>())
+                            .map_err(|x| x.to_string());
                         (Some(Product::MessagesBefore(result, channel)), v)
                     },
                     Channel::Private(ch) => {
-                        let result = ch.messages(client.cache_and_http.http.clone(), |x| x.before(search.0)).await.unwrap_or(vec![]);
-                        let result = result.into_iter().map(|x| LoadedMessage::from_message(x, &mut v)).collect::<Vec<_>>();
+                        // an empty page would close the gap for good
+                        let result = ch.messages(client.cache_and_http.http.clone(), |x| x.before(search.0)).await
+                            .map(|x| x.into_iter().map(|x| LoadedMessage::from_message(x, &mut v)).collect::<Vec<_>>())
+                            .map_err(|x| x.to_string());
                         (Some(Product::MessagesBefore(result, channel)), v)
                     }
                     Channel::Category(_) => panic!("Cannot get messages from a category!"),
@@ -46,21 +75,47 @@ impl Task {
             Task::GetMessagesAfter(channel, search) => {
                 match channel.clone() {
                     Channel::Guild(ch) => {
-                        let result = ch.messages(client.cache_and_http.http.clone(), |x| x.after(search.0)).await.unwrap_or(vec![]);
-                        let result = result.into_iter().map(|x| LoadedMessage::from_message(x, &mut v)).collect::<Vec<_>>();
-                        (Some(Product::MessagesBefore(result, channel)), v)
+                        // an empty page would close the gap for good
+                        let result = ch.messages(client.cache_and_http.http.clone(), |x| x.after(search.0)).await
+                            .map(|x| x.into_iter().map(|x| LoadedMessage::from_message(x, &mut v)).collect::<Vec<_>>())
+                            .map_err(|x| x.to_string());
+                        (Some(Product::MessagesAfter(result, channel, search)), v)
                     },
                     Channel::Private(ch) => {
-                        let result = ch.messages(client.cache_and_http.http.clone(), |x| x.after(search.0)).await.unwrap_or(vec![]);
-                        let result = result.into_iter().map(|x| LoadedMessage::from_message(x, &mut v)).collect::<Vec<_>>();
-                        (Some(Product::MessagesBefore(result, channel)), v)
+                        // an empty page would close the gap for good
+                        let result = ch.messages(client.cache_and_http.http.clone(), |x| x.after(search.0)).await
+                            .map(|x| x.into_iter().map(|x| LoadedMessage::from_message(x, &mut v)).collect::<Vec<_>>())
+                            .map_err(|x| x.to_string());
+                        (Some(Product::MessagesAfter(result, channel, search)), v)
                     }
                     Channel::Category(_) => panic!("Cannot get messages from a category!"),
                     _ => panic!()
                 }
             },
+            Task::GetHistory(channel, search, until) => {
+                let result = match client.cache_and_http.http.get_messages(channel.id().0, &format!("?before={}&limit={}", search.0, REQUEST_LEN)).await {
+                    Ok(val) => val,
+                    // an empty page would look like the beginning of the channel
+                    Err(why) => return (Some(Product::History(Err(why.to_string()), channel, true)), v),
+                };
+                let beginning = result.len() < REQUEST_LEN;
+                let oldest = result.last().map(|x| (x.id, x.timestamp.with_timezone(&Local)));
+                let result = result.into_iter().map(|x| LoadedMessage::from_message(x, &mut v)).collect::<Vec<_>>();
+                let next = match (until, oldest) {
+                    _ if beginning => None,
+                    (Until::Pages(val), Some((id, _))) if val > 1 => Some(Task::GetHistory(channel.clone(), id, Until::Pages(val - 1))),
+                    (Until::Date(date), Some((id, time))) if time > date => Some(Task::GetHistory(channel.clone(), id, until)),
+                    _ => None,
+                };
+                let done = next.is_none();
+                if let Some(task) = next {
+                    tokio::time::sleep(PAGE_DELAY).await;
+                    v.push(task);
+                }
+                (Some(Product::History(Ok(result), channel, done)), v)
+            },
             Task::GetNewMessages(channel) => {
-                let result = client.cache_and_http.http.get_messages(channel.id().0, "").await.expect("Could not get messages!");
+                let result = client.cache_and_http.http.get_messages(channel.id().0, "").await.unwrap_or_default();
                 let result = result.into_iter().map(|x| LoadedMessage::from_message(x, &mut v)).collect::<Vec<_>>();
                 (Some(Product::MessagesNew(result, channel)), v)
             },
@@ -68,6 +123,34 @@ impl Task {
                 std::thread::sleep(time);
                 (Some(Product::Can(val)), v)
             },
+            Task::Upload(channel, message, files) => {
+                let map = match message {
+                    serde_json::Value::Object(val) => val,
+                    _ => serde_json::Map::new(),
+                };
+                let count = files.len();
+                let files = files.iter().map(|x| AttachmentType::Path(std::path::Path::new(x)));
+                let result = client.cache_and_http.http.send_files(channel.0, files, map).await;
+                (Some(Product::Uploaded(result.map(|_| count).map_err(|x| x.to_string()))), v)
+            },
+            Task::GetArchivedThreads(channel) => {
+                let result = api::archived_threads(&client.cache_and_http.http, channel).await;
+                (Some(Product::Threads(result)), v)
+            },
+            Task::GetGuildInfo(guild, me) => {
+                let http = &client.cache_and_http.http;
+                let threads = api::active_threads(http, guild).await;
+                let (roles, owner, emojis) = match http.get_guild(guild.0).await {
+                    Ok(val) => (val.roles.into_iter().map(|x| x.1).collect(), val.owner_id == me, val.emojis.into_iter().map(|x| x.1).collect()),
+                    Err(_) => (Vec::new(), false, Vec::new()),
+                };
+                let mine = http.get_member(guild.0, me.0).await.ok().map(|x| x.roles);
+                (Some(Product::GuildInfo(guild, threads, roles, mine, owner, emojis)), v)
+            },
+            Task::GetUser(id) => {
+                let result = client.cache_and_http.http.get_user(id.0).await;
+                (Some(Product::User(id, result.map_err(|x| x.to_string()))), v)
+            },
         }
     }
 }
@@ -82,10 +165,16 @@ pub fn process(s: String) -> String {
 }
 
 pub enum Product {
-    MessagesBefore(Vec<LoadedMessage>, Channel),
-    MessagesAfter(Vec<LoadedMessage>, Channel),
+    MessagesBefore(Result<Vec<LoadedMessage>, String>, Channel),
+    MessagesAfter(Result<Vec<LoadedMessage>, String>, Channel, MessageId), // the message they follow
     MessagesNew(Vec<LoadedMessage>, Channel),
+    History(Result<Vec<LoadedMessage>, String>, Channel, bool), // whether it's the last page that was asked for
     Can(Response),
+    Uploaded(Result<usize, String>),
+    User(UserId, Result<User, String>),
+    Threads(Result<Vec<GuildChannel>, String>),
+    GuildInfo(GuildId, Result<Vec<GuildChannel>, String>, Vec<Role>, Option<Vec<RoleId>>, bool, Vec<Emoji>), // threads, roles, the user's roles, whether they own it, and its emojis
+
     CanSave,
     Killed,
 }
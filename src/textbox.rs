@@ -126,6 +126,9 @@ impl Textbox {
         }
         i
     }
+    pub fn text(&self) -> String {
+        self.text.join(&'\n').into_iter().collect()
+    }
     pub fn flush(&mut self) -> String {
         self.flag = true;
         self.cursor_pos = 0;
@@ -197,6 +200,12 @@ impl Textbox {
             self.cursor_pos += 1;
         }
     }
+    /// Moves the cursor to the end of the text.
+    pub fn end(&mut self) {
+        self.flag = true;
+        self.cursor_line = self.text.len() - 1;
+        self.cursor_pos = self.text[self.cursor_line].len();
+    }
     pub fn flag(&mut self) {
         self.flag = true;
     }